@@ -5,11 +5,29 @@ use std::str::FromStr;
 
 use anyhow::{Context, Result};
 use lsp_client::types::notification::{DidOpenTextDocument, Initialized};
-use lsp_client::types::request::{DocumentSymbolRequest, Initialize, References};
-use lsp_client::types::{DocumentSymbolResponse, Url};
-use serde_json::json;
-
-use lsp_client::StdIO;
+use lsp_client::types::request::{
+    CallHierarchyIncomingCalls, CallHierarchyPrepare, DocumentSymbolRequest, Initialize, References,
+};
+use lsp_client::types::{
+    CallHierarchyItem, DiagnosticSeverity, DocumentSymbol, DocumentSymbolResponse, ServerCapabilities,
+    SymbolKind, Url,
+};
+use serde_json::{json, Value};
+
+/// Per-request timeout for calls that can stall on a large/slow server, so
+/// one bad symbol doesn't block every symbol after it.
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Whether the server has reported any error-severity diagnostic for `uri`,
+/// which makes `references` results for that file unreliable.
+fn has_errors(client: &lsp_client::Client, uri: &Url) -> bool {
+    client.diagnostics().get(uri).is_some_and(|params| {
+        params
+            .diagnostics
+            .iter()
+            .any(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+    })
+}
 
 fn main() -> Result<()> {
     let args: Vec<String> = std::env::args().collect();
@@ -38,7 +56,7 @@ fn main() -> Result<()> {
 
     let mut child = start_lsp_server(cmd, args);
     let io = lsp_client::StdIO::new(&mut child);
-    let mut client = lsp_client::Client::new(io, false);
+    let mut client = lsp_client::Client::new(io);
 
     // start stderr logging thread
     let stderr = child.stderr.take().expect("Failed to take stderr");
@@ -52,7 +70,7 @@ fn main() -> Result<()> {
         }
     });
 
-    initialize_lsp(&mut client, &root_uri)?;
+    let capabilities = initialize_lsp(&mut client, &root_uri)?;
 
     let nodes: Vec<_> = files
         .iter()
@@ -61,7 +79,7 @@ fn main() -> Result<()> {
         .map(|n| n.as_str())
         .collect();
 
-    let edges = get_edges(&mut client, &root_uri, &files)?;
+    let edges = get_edges(&mut client, &root_uri, &files, &capabilities)?;
 
     println!(
         "{}",
@@ -91,67 +109,137 @@ fn read_uri(uri: &Url) -> Result<String> {
     }
 }
 
-fn get_edges(
-    client: &mut lsp_client::Client<StdIO>,
+fn symbols_of(client: &mut lsp_client::Client, uri: &Url) -> Result<Vec<DocumentSymbol>> {
+    let symbols = client.request::<DocumentSymbolRequest>(serde_json::from_value(json!({
+        "textDocument": {
+            "uri": uri.clone(),
+        },
+    }))?)?;
+
+    let symbols = match symbols {
+        Some(DocumentSymbolResponse::Nested(vec)) => {
+            let mut symbols = vec![];
+            let mut queue = vec;
+
+            while let Some(symbol) = queue.pop() {
+                symbols.push(symbol.clone());
+                if let Some(children) = symbol.children {
+                    queue.extend(children);
+                }
+            }
+
+            symbols
+        }
+        Some(DocumentSymbolResponse::Flat(flat)) => {
+            if flat.len() > 0 {
+                panic!("Got non-empty flat documentSymbol response")
+            }
+
+            vec![]
+        }
+        None => vec![],
+    };
+
+    Ok(symbols)
+}
+
+/// Key identifying a [`CallHierarchyItem`] uniquely enough to dedupe items
+/// and edges across overlapping `incomingCalls` results.
+fn call_hierarchy_item_key(item: &CallHierarchyItem) -> String {
+    format!(
+        "{}#{}@{}:{}",
+        item.uri,
+        item.name,
+        item.selection_range.start.line,
+        item.selection_range.start.character
+    )
+}
+
+/// Builds symbol-to-symbol call edges via `callHierarchy/incomingCalls`,
+/// which gives exact caller/callee pairs instead of the file-level
+/// over-approximation plain `references` gives. Starts from every
+/// function/method symbol and walks callers in, ignoring ones outside
+/// `root_uri`.
+fn get_edges_by_call_hierarchy(
+    client: &mut lsp_client::Client,
     root_uri: &Url,
     files: &[Url],
-) -> Result<HashSet<(String, String)>> {
-    let mut edges: HashSet<(String, String)> = HashSet::new();
+) -> Result<Value> {
+    let mut items = serde_json::Map::new();
+    let mut edges = vec![];
+    let mut seen_edges = HashSet::new();
 
     for uri in files {
-        eprintln!("Loading uri: {}", uri.as_str());
+        eprintln!("Processing uri: {}", uri.as_str());
 
-        client.notify::<DidOpenTextDocument>(serde_json::from_value(json!({
-            "textDocument": {
-            "uri": uri.clone(),
-            "languageId": "",
-            "version": 1,
-            "text": read_uri(uri)?,
+        for symbol in symbols_of(client, uri)? {
+            if !matches!(symbol.kind, SymbolKind::FUNCTION | SymbolKind::METHOD) {
+                continue;
             }
-        }))?)?;
-    }
 
-    eprintln!("Waiting 3 seconds for LSP to index code...");
-    std::thread::sleep(std::time::Duration::from_secs(3));
+            let callees = client.request::<CallHierarchyPrepare>(serde_json::from_value(json!({
+                "textDocument": { "uri": uri.clone() },
+                "position": symbol.selection_range.start,
+            }))?)?;
+
+            for callee in callees.unwrap_or_default() {
+                let callee_key = call_hierarchy_item_key(&callee);
+                items
+                    .entry(callee_key.clone())
+                    .or_insert_with(|| json!({ "file": callee.uri, "name": callee.name, "range": callee.selection_range }));
+
+                let callers = match client.request_with_timeout::<CallHierarchyIncomingCalls>(
+                    serde_json::from_value(json!({ "item": callee }))?,
+                    REQUEST_TIMEOUT,
+                ) {
+                    Ok(callers) => callers.unwrap_or_default(),
+                    Err(err) => {
+                        eprintln!("Skipping incoming calls for {}: {}", callee_key, err);
+                        continue;
+                    }
+                };
 
-    for uri in files {
-        eprintln!("Processing uri: {}", uri.as_str());
+                for caller in callers.into_iter().map(|call| call.from) {
+                    if !caller.uri.as_str().starts_with(root_uri.as_str()) {
+                        continue;
+                    }
 
-        let symbols = client.request::<DocumentSymbolRequest>(serde_json::from_value(json!({
-            "textDocument": {
-                "uri": uri.clone(),
-            },
-        }))?)?;
+                    if has_errors(client, &caller.uri) {
+                        continue;
+                    }
 
-        let symbols = match symbols {
-            Some(DocumentSymbolResponse::Nested(vec)) => {
-                let mut symbols = vec![];
-                let mut queue = vec;
+                    let caller_key = call_hierarchy_item_key(&caller);
+                    items
+                        .entry(caller_key.clone())
+                        .or_insert_with(|| json!({ "file": caller.uri, "name": caller.name, "range": caller.selection_range }));
 
-                while let Some(symbol) = queue.pop() {
-                    symbols.push(symbol.clone());
-                    if let Some(children) = symbol.children {
-                        queue.extend(children);
+                    if seen_edges.insert((caller_key.clone(), callee_key.clone())) {
+                        eprintln!("Found call: {} -> {}", caller_key, callee_key);
+                        edges.push(json!({ "from": caller_key, "to": callee_key }));
                     }
                 }
-
-                symbols
             }
-            Some(DocumentSymbolResponse::Flat(flat)) => {
-                if flat.len() > 0 {
-                    panic!("Got non-empty flat documentSymbol response")
-                }
+        }
+    }
 
-                vec![]
-            }
-            None => vec![],
-        };
+    Ok(json!({ "calls": items, "edges": edges }))
+}
 
-        for symbol in symbols.iter() {
+fn get_edges_by_references(
+    client: &mut lsp_client::Client,
+    root_uri: &Url,
+    files: &[Url],
+) -> Result<Value> {
+    let mut edges: HashSet<(String, String)> = HashSet::new();
+
+    for uri in files {
+        eprintln!("Processing uri: {}", uri.as_str());
+
+        for symbol in symbols_of(client, uri)? {
             eprintln!("Processing symbol: {:?} {}", symbol.kind, symbol.name);
 
-            let Some(references) =
-                client.request::<References>(serde_json::from_value(json!({
+            let references = client.request_with_timeout::<References>(
+                serde_json::from_value(json!({
                     "textDocument": {
                         "uri": uri.clone(),
                     },
@@ -162,9 +250,17 @@ fn get_edges(
                     "context": {
                         "includeDeclaration": false,
                     },
-                }))?)?
-            else {
-                continue;
+                }))?,
+                REQUEST_TIMEOUT,
+            );
+
+            let references = match references {
+                Ok(Some(references)) => references,
+                Ok(None) => continue,
+                Err(err) => {
+                    eprintln!("Skipping symbol {}: {}", symbol.name, err);
+                    continue;
+                }
             };
 
             eprintln!("Got references: {}", references.len());
@@ -178,22 +274,70 @@ fn get_edges(
                     continue;
                 }
 
+                // the file containing the reference failed to compile, so its
+                // reported references can't be trusted
+                if has_errors(client, &reference.uri) {
+                    continue;
+                }
+
                 edges.insert((reference.uri.to_string(), uri.to_string()));
             }
         }
     }
 
-    Ok(edges)
+    Ok(json!(edges))
 }
 
-fn initialize_lsp(client: &mut lsp_client::Client<StdIO>, root_uri: &Url) -> Result<()> {
+fn get_edges(
+    client: &mut lsp_client::Client,
+    root_uri: &Url,
+    files: &[Url],
+    capabilities: &ServerCapabilities,
+) -> Result<Value> {
+    for uri in files {
+        eprintln!("Loading uri: {}", uri.as_str());
+
+        client.notify::<DidOpenTextDocument>(serde_json::from_value(json!({
+            "textDocument": {
+            "uri": uri.clone(),
+            "languageId": "",
+            "version": 1,
+            "text": read_uri(uri)?,
+            }
+        }))?)?;
+    }
+
+    eprintln!("Waiting for LSP to finish indexing...");
+    client.wait_for_progress_idle(std::time::Duration::from_secs(30))?;
+
+    for (method, params) in client.poll_notifications() {
+        eprintln!("Got notification while indexing: {} {}", method, params);
+    }
+
+    // call hierarchy gives precise symbol-to-symbol edges; fall back to
+    // file-to-file reference matching for servers that don't support it.
+    if capabilities.call_hierarchy_provider.is_some() {
+        get_edges_by_call_hierarchy(client, root_uri, files)
+    } else {
+        eprintln!("Server has no call hierarchy support, falling back to references");
+        get_edges_by_references(client, root_uri, files)
+    }
+}
+
+fn initialize_lsp(client: &mut lsp_client::Client, root_uri: &Url) -> Result<ServerCapabilities> {
     let initialize = client.request::<Initialize>(serde_json::from_value(json!({
         "capabilities": {
             "textDocument": {
                 "documentSymbol": {
                     "hierarchicalDocumentSymbolSupport": true,
                 },
-            }
+                "callHierarchy": {
+                    "dynamicRegistration": false,
+                },
+            },
+            "window": {
+                "workDoneProgress": true,
+            },
         },
         "workspaceFolders": [{
             "uri": root_uri,
@@ -205,11 +349,15 @@ fn initialize_lsp(client: &mut lsp_client::Client<StdIO>, root_uri: &Url) -> Res
         anyhow::bail!("Server is not 'documentSymbol' provider");
     }
 
-    if initialize.capabilities.references_provider.is_none() {
-        anyhow::bail!("Server is not 'references' provider");
+    // references is only required as a fallback for servers that don't
+    // support call hierarchy; one that advertises both is fine either way.
+    if initialize.capabilities.call_hierarchy_provider.is_none()
+        && initialize.capabilities.references_provider.is_none()
+    {
+        anyhow::bail!("Server is neither a 'callHierarchy' nor 'references' provider");
     }
 
     client.notify::<Initialized>(None)?;
 
-    Ok(())
+    Ok(initialize.capabilities)
 }
@@ -1,8 +1,25 @@
 use jsonrpc::types::JsonRpcResult;
-use lsp_client::client::Client;
+use lsp_client::clients::Client;
 use lsp_types::{request::*, *};
 use petgraph::{algo::has_path_connecting, graph::DiGraph, visit::NodeRef};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+fn walk_nested_symbols(
+    uri: &Url,
+    children: Vec<DocumentSymbol>,
+    fn_definitions: &mut Vec<(Url, DocumentSymbol)>,
+) {
+    for child in children {
+        if matches!(child.kind, SymbolKind::FUNCTION | SymbolKind::METHOD) {
+            fn_definitions.push((uri.clone(), child.clone()));
+        }
+
+        if let Some(children) = child.children {
+            walk_nested_symbols(uri, children, fn_definitions);
+        }
+    }
+}
 
 pub async fn get_project_functions(
     project_files: &[PathBuf],
@@ -13,26 +30,41 @@ pub async fn get_project_functions(
         .map(|file| Url::from_file_path(file).unwrap())
         .collect::<Vec<_>>();
 
+    // Collect the partial document symbols the server streams back through
+    // `$/progress` for each file's token and merge them with the final
+    // response. Each request is only sent when its future is awaited below,
+    // one file after another, and this function returns only once every file
+    // has been answered.
     let mut symbol_futures = vec![];
     for uri in &project_file_uris {
-        symbol_futures.push(
-            client.request::<DocumentSymbolRequest, ()>(DocumentSymbolParams {
+        let partial_symbols = Arc::new(Mutex::new(Vec::new()));
+        let sink = partial_symbols.clone();
+        let token = client.mint_progress_token();
+
+        let request = client.request_streaming::<DocumentSymbolRequest, ()>(
+            DocumentSymbolParams {
                 text_document: lsp_types::TextDocumentIdentifier { uri: uri.clone() },
                 partial_result_params: lsp_types::PartialResultParams {
-                    partial_result_token: None,
+                    partial_result_token: Some(token.clone()),
                 },
                 work_done_progress_params: WorkDoneProgressParams {
-                    work_done_token: None,
+                    work_done_token: Some(token.clone()),
                 },
-            }),
+            },
+            token,
+            move |value| {
+                if let Ok(mut symbols) = serde_json::from_value::<Vec<DocumentSymbol>>(value) {
+                    sink.lock().unwrap().append(&mut symbols);
+                }
+            },
         );
+        symbol_futures.push((partial_symbols, request));
     }
 
     let mut fn_definitions = vec![];
-    for (uri, s) in project_file_uris.iter().zip(symbol_futures.into_iter()) {
+    for (uri, (partial_symbols, s)) in project_file_uris.iter().zip(symbol_futures.into_iter()) {
         let response = match s.await.unwrap().result {
-            JsonRpcResult::Result(Some(response)) => response,
-            JsonRpcResult::Result(None) => panic!("Got no symbols in doc: {}", uri),
+            JsonRpcResult::Result(response) => response,
             JsonRpcResult::Error {
                 code,
                 message,
@@ -43,30 +75,19 @@ pub async fn get_project_functions(
             ),
         };
 
+        let mut symbols = Arc::try_unwrap(partial_symbols)
+            .map(|lock| lock.into_inner().unwrap())
+            .unwrap_or_default();
+
         match response {
-            DocumentSymbolResponse::Flat(_) => {
+            Some(DocumentSymbolResponse::Flat(_)) => {
                 panic!("Got flat document symbol");
             }
-            DocumentSymbolResponse::Nested(nested) => {
-                fn walk_nested_symbols(
-                    uri: &Url,
-                    children: Vec<DocumentSymbol>,
-                    fn_definitions: &mut Vec<(Url, DocumentSymbol)>,
-                ) {
-                    for child in children {
-                        if matches!(child.kind, SymbolKind::FUNCTION | SymbolKind::METHOD) {
-                            fn_definitions.push((uri.clone(), child.clone()));
-                        }
-
-                        if let Some(children) = child.children {
-                            walk_nested_symbols(uri, children, fn_definitions);
-                        }
-                    }
-                }
-
-                walk_nested_symbols(uri, nested, &mut fn_definitions);
-            }
+            Some(DocumentSymbolResponse::Nested(nested)) => symbols.extend(nested),
+            None => {}
         };
+
+        walk_nested_symbols(uri, symbols, &mut fn_definitions);
     }
 
     fn_definitions
@@ -82,57 +103,96 @@ pub async fn get_functions_graph(
 ) {
     let mut fn_call_items = vec![];
     let mut fn_calls_futures = vec![];
+
+    // Group by file and fire every symbol's CallHierarchyPrepare request for
+    // that file as a single batch round trip instead of N sequential awaits.
+    let mut fn_definitions_by_file: Vec<(&Url, Vec<&DocumentSymbol>)> = vec![];
     for (file, symbol) in fn_definitions {
-        let fn_definition_items = match client
-            .request::<CallHierarchyPrepare, ()>(CallHierarchyPrepareParams {
-                text_document_position_params: TextDocumentPositionParams {
-                    text_document: TextDocumentIdentifier { uri: file.clone() },
-                    position: symbol.selection_range.start,
-                },
-                work_done_progress_params: WorkDoneProgressParams {
-                    work_done_token: None,
-                },
+        match fn_definitions_by_file.last_mut() {
+            Some((last_file, symbols)) if *last_file == file => symbols.push(symbol),
+            _ => fn_definitions_by_file.push((file, vec![symbol])),
+        }
+    }
+
+    for (file, symbols) in fn_definitions_by_file {
+        let entries = symbols
+            .iter()
+            .map(|symbol| {
+                client.prepare_request::<CallHierarchyPrepare>(CallHierarchyPrepareParams {
+                    text_document_position_params: TextDocumentPositionParams {
+                        text_document: TextDocumentIdentifier { uri: file.clone() },
+                        position: symbol.selection_range.start,
+                    },
+                    work_done_progress_params: WorkDoneProgressParams {
+                        work_done_token: None,
+                    },
+                })
             })
-            .await
-            .unwrap()
-            .result
-        {
-            JsonRpcResult::Result(Some(items)) => items
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let responses = client.batch(entries).await.unwrap();
+
+        for response in responses {
+            let response = response.unwrap();
+
+            let fn_definition_items = match response.get("result") {
+                Some(result) => serde_json::from_value::<Option<Vec<CallHierarchyItem>>>(result.clone())
+                    .unwrap()
+                    .unwrap_or_default(),
+                None => {
+                    eprintln!("Got error for call hierarchy prepare: {:?}", response.get("error"));
+                    vec![]
+                }
+            };
+
+            for fn_definition_item in fn_definition_items
                 .into_iter()
-                .filter(|i| matches!(i.kind, SymbolKind::FUNCTION | SymbolKind::METHOD)),
-            JsonRpcResult::Result(None) => todo!(),
-            JsonRpcResult::Error {
-                code: _,
-                message: _,
-                data: _,
-            } => todo!(),
-        };
+                .filter(|i| matches!(i.kind, SymbolKind::FUNCTION | SymbolKind::METHOD))
+            {
+                fn_call_items.push(fn_definition_item.clone());
 
-        for fn_definition_item in fn_definition_items {
-            fn_call_items.push(fn_definition_item.clone());
+                let partial_calls = Arc::new(Mutex::new(Vec::new()));
+                let sink = partial_calls.clone();
+                let token = client.mint_progress_token();
 
-            let request = client.request::<CallHierarchyIncomingCalls, ()>(
-                CallHierarchyIncomingCallsParams {
-                    item: fn_definition_item.clone(),
-                    partial_result_params: lsp_types::PartialResultParams {
-                        partial_result_token: None,
+                let request = client.request_streaming::<CallHierarchyIncomingCalls, ()>(
+                    CallHierarchyIncomingCallsParams {
+                        item: fn_definition_item.clone(),
+                        partial_result_params: lsp_types::PartialResultParams {
+                            partial_result_token: Some(token.clone()),
+                        },
+                        work_done_progress_params: WorkDoneProgressParams {
+                            work_done_token: Some(token.clone()),
+                        },
                     },
-                    work_done_progress_params: WorkDoneProgressParams {
-                        work_done_token: None,
+                    token,
+                    move |value| {
+                        if let Ok(mut calls) =
+                            serde_json::from_value::<Vec<CallHierarchyIncomingCall>>(value)
+                        {
+                            sink.lock().unwrap().append(&mut calls);
+                        }
                     },
-                },
-            );
-            fn_calls_futures.push((fn_definition_item, request));
+                );
+                fn_calls_futures.push((fn_definition_item, partial_calls, request));
+            }
         }
     }
 
     let mut fn_calls = vec![];
-    for (symbol, fn_call_future) in fn_calls_futures {
+    for (symbol, partial_calls, fn_call_future) in fn_calls_futures {
         let response = fn_call_future.await.unwrap();
 
+        let mut calls = Arc::try_unwrap(partial_calls)
+            .map(|lock| lock.into_inner().unwrap())
+            .unwrap_or_default();
+
         match response.result {
-            JsonRpcResult::Result(Some(result)) => {
-                for call in result {
+            JsonRpcResult::Result(result) => {
+                calls.extend(result.unwrap_or_default());
+
+                for call in calls {
                     if call
                         .from
                         .uri
@@ -147,9 +207,6 @@ pub async fn get_functions_graph(
                     }
                 }
             }
-            JsonRpcResult::Result(None) => {
-                todo!()
-            }
             JsonRpcResult::Error {
                 code,
                 message,
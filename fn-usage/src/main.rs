@@ -1,6 +1,6 @@
 use std::{path::PathBuf, process::Stdio, time::Duration};
 
-use jsonrpc::types::{JsonRpcResult, Response};
+use jsonrpc::types::JsonRpcResult;
 use lsp_client::clients;
 use lsp_types::{notification::*, request::*, *};
 use tokio::process::{Child, Command};
@@ -28,15 +28,21 @@ async fn main() {
         return;
     };
 
-    let mut child = start_server(server_cmd);
     let root_uri =
         Url::from_file_path(&PathBuf::from(project_root).canonicalize().unwrap()).unwrap();
 
-    let stdin = child.stdin.take().unwrap();
-    let stdout = child.stdout.take().unwrap();
-    let stderr = child.stderr.take().unwrap();
-
-    let (client, handles) = clients::stdio_client(stdin, stdout, stderr);
+    // A `tcp://host:port` server_cmd connects to a server already listening
+    // on a socket instead of spawning one.
+    let (client, handles) = if let Some(addr) = server_cmd.strip_prefix("tcp://") {
+        clients::tcp_client(addr).await.unwrap()
+    } else {
+        let mut child = start_server(server_cmd);
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+
+        clients::stdio_client(stdin, stdout, stderr)
+    };
 
     let response = client
         .request::<Initialize, InitializeError>(InitializeParams {
@@ -49,6 +55,10 @@ async fn main() {
                     }),
                     ..Default::default()
                 }),
+                window: Some(WindowClientCapabilities {
+                    work_done_progress: Some(true),
+                    ..Default::default()
+                }),
                 ..Default::default()
             },
             ..Default::default()
@@ -78,53 +88,8 @@ async fn main() {
         .flat_map(|fs| fs.map(|f| f.unwrap()))
         .collect::<Vec<_>>();
 
-    // wait for server to start
-    let uri = Url::from_file_path(project_files.first().unwrap()).unwrap();
-    // client
-    //     .notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
-    //         text_document: TextDocumentItem {
-    //             uri: uri.clone(),
-    //             language_id: "unknown".to_string(),
-    //             version: 0,
-    //             text: "".to_string(),
-    //         },
-    //     })
-    //     .unwrap();
-
-    while let Ok(Response {
-        jsonrpc: _,
-        result,
-        id: _,
-    }) = client
-        .request::<FoldingRangeRequest, ()>(FoldingRangeParams {
-            text_document: TextDocumentIdentifier { uri: uri.clone() },
-            partial_result_params: lsp_types::PartialResultParams {
-                partial_result_token: None,
-            },
-            work_done_progress_params: WorkDoneProgressParams {
-                work_done_token: None,
-            },
-        })
-        .await
-    {
-        match result {
-            JsonRpcResult::Result(_) => break,
-            JsonRpcResult::Error {
-                code,
-                message,
-                data: _,
-            } => {
-                println!("error {}:\n{}", code, message);
-                assert!(
-                    code == -32801,
-                    "got unexpected error {}, message: {}",
-                    code,
-                    message
-                );
-                tokio::time::sleep(Duration::from_millis(500)).await;
-            }
-        }
-    }
+    eprintln!("Waiting for server to finish indexing...");
+    client.wait_until_ready(Duration::from_secs(30)).await.unwrap();
 
     let fn_definitions = fn_usage::get_project_functions(&project_files, &client).await;
 
@@ -1,4 +1,3 @@
-use jsonrpc::types::{JsonRpcResult, Response};
 use lsp_client::clients;
 use lsp_types::{notification::*, request::*, *};
 use std::{path::Path, process::Stdio, time::Duration};
@@ -41,6 +40,10 @@ async fn _test_rust_analyzer() {
                     }),
                     ..Default::default()
                 }),
+                window: Some(WindowClientCapabilities {
+                    work_done_progress: Some(true),
+                    ..Default::default()
+                }),
                 ..Default::default()
             },
             ..Default::default()
@@ -61,42 +64,10 @@ async fn _test_rust_analyzer() {
     let short_project_files = get_short_files(&project_files);
     insta::assert_debug_snapshot!(short_project_files);
 
-    // wait for server to start
-    let uri = Url::from_file_path(project_files.first().unwrap()).unwrap();
-    while let Ok(Response {
-        jsonrpc: _,
-        result,
-        id: _,
-    }) = client
-        .request::<FoldingRangeRequest, ()>(FoldingRangeParams {
-            text_document: TextDocumentIdentifier { uri: uri.clone() },
-            partial_result_params: lsp_types::PartialResultParams {
-                partial_result_token: None,
-            },
-            work_done_progress_params: WorkDoneProgressParams {
-                work_done_token: None,
-            },
-        })
+    client
+        .wait_until_ready(Duration::from_secs(30))
         .await
-    {
-        match result {
-            JsonRpcResult::Result(_) => break,
-            JsonRpcResult::Error {
-                code,
-                message,
-                data: _,
-            } => {
-                println!("error {}:\n{}", code, message);
-                assert!(
-                    code == -32801,
-                    "got unexpected error {}, message: {}",
-                    code,
-                    message
-                );
-                tokio::time::sleep(Duration::from_millis(500)).await;
-            }
-        }
-    }
+        .unwrap();
 
     let fn_definitions = fn_usage::get_project_functions(&project_files, &client).await;
 
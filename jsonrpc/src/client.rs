@@ -2,7 +2,7 @@ use anyhow::{Context, Result};
 use serde::{de::DeserializeOwned, Serialize};
 use tokio::sync::oneshot;
 
-use crate::types::{Notification, Request, Response};
+use crate::types::{Message, Notification, Request, Response};
 use serde_json::Value;
 use std::{
     collections::HashMap,
@@ -13,9 +13,18 @@ use std::{
     thread::JoinHandle,
 };
 
+/// Handles a server-initiated request for `method`, returning the `result`
+/// value to send back.
+pub type RequestHandler = Box<dyn FnMut(Value) -> Value + Send>;
+/// Handles a notification for `method`, e.g. `window/logMessage` or
+/// `textDocument/publishDiagnostics`.
+pub type NotificationHandler = Box<dyn FnMut(Value) + Send>;
+
 pub struct Client {
     client_tx: Sender<String>,
     pending_responses: Arc<Mutex<HashMap<i64, oneshot::Sender<Value>>>>,
+    request_handlers: Arc<Mutex<HashMap<String, RequestHandler>>>,
+    notification_handlers: Arc<Mutex<HashMap<String, NotificationHandler>>>,
     handle: JoinHandle<()>,
     kill_thread_tx: Sender<()>,
 }
@@ -32,8 +41,13 @@ impl Drop for Client {
 impl Client {
     pub fn new(client_tx: Sender<String>, server_rx: Receiver<String>) -> Self {
         let pending_responses = Arc::new(Mutex::new(HashMap::<i64, oneshot::Sender<Value>>::new()));
+        let request_handlers = Arc::new(Mutex::new(HashMap::<String, RequestHandler>::new()));
+        let notification_handlers = Arc::new(Mutex::new(HashMap::<String, NotificationHandler>::new()));
 
         let pending_responses_clone = pending_responses.clone();
+        let request_handlers_clone = request_handlers.clone();
+        let notification_handlers_clone = notification_handlers.clone();
+        let client_tx_clone = client_tx.clone();
 
         let (kill_thread_tx, kill_thread_rx) = std::sync::mpsc::channel();
         let handle = std::thread::spawn(move || loop {
@@ -44,33 +58,88 @@ impl Client {
             if let Ok(response) = server_rx.try_recv() {
                 let value = serde_json::from_str::<Value>(&response)
                     .expect("failed to deserialize response");
-
-                let id = value
-                    .as_object()
-                    .expect("got non-object response")
-                    .get("id")
-                    .expect("got response without id")
-                    .as_i64()
-                    .expect("got non i64 id");
-
-                pending_responses_clone
-                    .lock()
-                    .expect("failed to acquire lock")
-                    .remove(&id)
-                    .expect("no pending response matching server response")
-                    .send(value)
-                    .expect("failed to send response to pending response");
+                let message: Message = serde_json::from_value(value.clone())
+                    .expect("failed to classify incoming message");
+
+                match message {
+                    Message::Response(_) => {
+                        let id = value
+                            .as_object()
+                            .expect("got non-object response")
+                            .get("id")
+                            .expect("got response without id")
+                            .as_i64()
+                            .expect("got non i64 id");
+
+                        pending_responses_clone
+                            .lock()
+                            .expect("failed to acquire lock")
+                            .remove(&id)
+                            .expect("no pending response matching server response")
+                            .send(value)
+                            .expect("failed to send response to pending response");
+                    }
+                    Message::Request(request) => {
+                        let result = match request_handlers_clone
+                            .lock()
+                            .expect("failed to acquire lock")
+                            .get_mut(&request.method)
+                        {
+                            Some(handler) => handler(request.params),
+                            None => Value::Null,
+                        };
+
+                        let response = serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "id": request.id,
+                            "result": result,
+                        });
+                        if let Ok(response) = serde_json::to_string(&response) {
+                            let _ = client_tx_clone.send(response);
+                        }
+                    }
+                    Message::Notification(notification) => {
+                        if let Some(handler) = notification_handlers_clone
+                            .lock()
+                            .expect("failed to acquire lock")
+                            .get_mut(&notification.method)
+                        {
+                            handler(notification.params);
+                        }
+                    }
+                }
             }
         });
 
         Self {
             client_tx,
             pending_responses,
+            request_handlers,
+            notification_handlers,
             handle,
             kill_thread_tx,
         }
     }
 
+    /// Registers a handler for a server-initiated request named `method`.
+    /// Replaces any handler previously registered for the same method.
+    pub fn on_request(&self, method: &str, mut handler: impl FnMut(Value) -> Value + Send + 'static) {
+        self.request_handlers
+            .lock()
+            .expect("failed to acquire lock")
+            .insert(method.to_string(), Box::new(move |params| handler(params)));
+    }
+
+    /// Registers a handler for a notification named `method`, e.g. to
+    /// collect `textDocument/publishDiagnostics`. Replaces any handler
+    /// previously registered for the same method.
+    pub fn on_notification(&self, method: &str, mut handler: impl FnMut(Value) + Send + 'static) {
+        self.notification_handlers
+            .lock()
+            .expect("failed to acquire lock")
+            .insert(method.to_string(), Box::new(move |params| handler(params)));
+    }
+
     pub async fn request<P, R, E>(
         &self,
         request: Request<P>,
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
@@ -35,6 +36,39 @@ pub enum JsonRpcResult<T, E> {
     },
 }
 
+/// A frame received from the server, classified by shape rather than by a
+/// tag in the payload (the base protocol doesn't have one): a reply to one
+/// of our own requests, a one-way notification, or a server-initiated
+/// request expecting a response back.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Message {
+    Response(Response<Value, Value>),
+    Request(ServerRequest),
+    Notification(ServerNotification),
+}
+
+/// A server-initiated request, e.g. `workspace/configuration`. Requires a
+/// `Response` to be sent back carrying the same `id`.
+#[derive(Deserialize, Debug)]
+pub struct ServerRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    pub id: i64,
+}
+
+/// A one-way notification from the server, e.g. `window/logMessage` or
+/// `textDocument/publishDiagnostics`.
+#[derive(Deserialize, Debug)]
+pub struct ServerNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,4 +1,4 @@
-use std::io::{BufRead, BufReader, Write};
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::process::{Child, ChildStdout};
 use std::sync::{
     atomic::AtomicBool,
@@ -69,54 +69,91 @@ impl ChildStdioChannel {
     }
 }
 
+/// Error reading a single `Content-Length`-framed message off the child's stdout.
+#[derive(Debug)]
+enum FrameError {
+    /// The stream closed before a full header block or body was read.
+    Eof,
+    /// A header line didn't look like `Name: value` or was missing `Content-Length`.
+    MalformedHeader(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for FrameError {
+    fn from(err: io::Error) -> Self {
+        FrameError::Io(err)
+    }
+}
+
+/// Reads one LSP base-protocol message: a block of `\r\n`-terminated headers
+/// (only `Content-Length` is required, `Content-Type` is accepted and ignored),
+/// followed by the blank separator line, followed by exactly `Content-Length` bytes.
+fn read_message(rx: &mut BufReader<ChildStdout>) -> Result<String, FrameError> {
+    let mut content_length = None;
+
+    loop {
+        let mut line = String::new();
+        let bytes_read = rx.read_line(&mut line)?;
+        if bytes_read == 0 {
+            return Err(FrameError::Eof);
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| FrameError::MalformedHeader(line.to_string()))?;
+
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| FrameError::MalformedHeader(line.to_string()))?,
+            );
+        }
+        // Content-Type and any other headers are accepted and ignored.
+    }
+
+    let content_length =
+        content_length.ok_or_else(|| FrameError::MalformedHeader("missing Content-Length".to_string()))?;
+
+    let mut content = vec![0u8; content_length];
+    rx.read_exact(&mut content)
+        .map_err(|_| FrameError::Eof)?;
+
+    String::from_utf8(content).map_err(|err| FrameError::MalformedHeader(err.to_string()))
+}
+
 fn stdout_proxy(
     mut rx: BufReader<ChildStdout>,
     tx: Sender<String>,
     stop_flag: Arc<AtomicBool>,
 ) -> JoinHandle<()> {
     std::thread::spawn(move || {
-        let mut next_content_length = None;
-        let mut next_content_type = None;
-
         while !stop_flag.load(std::sync::atomic::Ordering::Relaxed) {
-            let mut line = String::new();
-            if rx.read_line(&mut line).is_err() {
-                break;
-            }
-
-            let words = line.split_ascii_whitespace().collect::<Vec<_>>();
-            match (
-                words.as_slice(),
-                &mut next_content_length,
-                &mut next_content_type,
-            ) {
-                (["Content-Length:", content_length], None, None) => {
-                    next_content_length = Some(content_length.parse().unwrap())
-                }
-                (["Content-Type:", content_type], Some(_), None) => {
-                    next_content_type = Some(content_type.to_string())
-                }
-                ([], Some(content_length), _) => {
-                    let mut content = Vec::with_capacity(*content_length);
-                    let mut bytes_left = *content_length;
-                    while bytes_left > 0 {
-                        let read_bytes = rx.read_until(b'}', &mut content).unwrap();
-                        bytes_left -= read_bytes;
+            match read_message(&mut rx) {
+                Ok(content) => {
+                    if tx.send(content).is_err() {
+                        // receiver dropped, nothing left to forward to
+                        break;
                     }
-
-                    let content = String::from_utf8(content).unwrap();
-                    tx.send(content).unwrap();
-
-                    next_content_length = None;
-                    next_content_type = None;
                 }
-                // empty line only for server termination
-                ([], None, None) => {
+                Err(FrameError::Eof) => {
                     println!("Server shutting down...");
                     break;
                 }
-                unexpected => panic!("Got unexpected stdout: {:?}", unexpected),
-            };
+                Err(FrameError::MalformedHeader(header)) => {
+                    eprintln!("Dropping malformed frame, bad header: {:?}", header);
+                }
+                Err(FrameError::Io(err)) => {
+                    eprintln!("Error reading from server stdout: {}", err);
+                    break;
+                }
+            }
         }
     })
 }
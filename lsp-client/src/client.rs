@@ -1,90 +1,347 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    mpsc, Arc, Mutex,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
 use anyhow::{Context, Result};
-use lsp_types::{notification::Notification as LspNotification, request::Request as LspRequest};
-use serde_json::Value;
+use lsp_types::{
+    notification::Notification as LspNotification, request::Request as LspRequest,
+    PublishDiagnosticsParams, Url,
+};
+use serde_json::{json, Value};
+
+use crate::jsonrpc::{self, RequestId, ServerMessage};
 
-use crate::jsonrpc;
+/// An in-flight request's method name alongside the channel its response (or
+/// a cancellation) is delivered on -- modeled on `lsp-server`'s `req_queue`,
+/// which keeps the same pairing so a response can be matched to what was
+/// actually asked, and so [`Client::cancel`] doesn't need a second lookup.
+struct PendingRequest {
+    method: String,
+    sender: mpsc::Sender<Value>,
+}
 
-pub trait StringIO {
+pub trait StringIO: Send + 'static {
     fn send(&mut self, msg: &str) -> Result<()>;
     fn recv(&mut self) -> Result<String>;
 }
 
+/// Handles a server-initiated request for `method`, returning the `result`
+/// value to send back.
+pub type RequestHandler = Box<dyn FnMut(Value) -> Value + Send>;
+/// Handles a server-initiated notification for `method`.
+pub type NotificationHandler = Box<dyn FnMut(Value) + Send>;
+
+/// How many times [`Client::request`] retries a transient JSON-RPC error
+/// before giving up and surfacing it to the caller.
+const MAX_TRANSIENT_RETRIES: u32 = 5;
+/// Base delay before the first retry; each subsequent retry waits longer
+/// (`attempt * RETRY_BACKOFF`), so a server that's briefly busy gets more
+/// room to recover before we give up.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A JSON-RPC error the server sent back, classified by its code so callers
+/// (and [`Client::request`]'s own retry loop) can tell a transient hiccup
+/// from a fatal mistake without hand-rolling the check themselves.
 #[derive(Debug)]
-pub struct Error {
-    code: i64,
-    message: String,
-    data: serde_json::Value,
+pub enum Error {
+    /// `ContentModified` (-32801) or `RequestCancelled` (-32800): the
+    /// server's state moved out from under the request rather than the
+    /// request being wrong. Safe, and usually necessary, to retry.
+    Transient {
+        code: i64,
+        message: String,
+        data: Value,
+    },
+    /// `MethodNotFound` (-32601) or `InvalidParams` (-32602): the request
+    /// itself is malformed or unsupported. Retrying without changing it
+    /// would just fail the same way again.
+    Fatal {
+        code: i64,
+        message: String,
+        data: Value,
+    },
+    /// Any other JSON-RPC error code.
+    Rpc {
+        code: i64,
+        message: String,
+        data: Value,
+    },
+    /// The request/response failed below the JSON-RPC layer: serializing
+    /// the request, writing it, reading a reply, or deserializing one.
+    Io(anyhow::Error),
+}
+
+impl Error {
+    fn from_jsonrpc(code: i64, message: String, data: Value) -> Self {
+        match code {
+            -32801 | -32800 => Error::Transient {
+                code,
+                message,
+                data,
+            },
+            -32601 | -32602 => Error::Fatal {
+                code,
+                message,
+                data,
+            },
+            _ => Error::Rpc {
+                code,
+                message,
+                data,
+            },
+        }
+    }
+
+    /// Whether this error is worth retrying -- i.e. the server itself said
+    /// its state changed out from under the request, not that the request
+    /// was wrong.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::Transient { .. })
+    }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Error {} - {}: {}", self.code, self.message, self.data)
+        match self {
+            Error::Transient {
+                code,
+                message,
+                data,
+            }
+            | Error::Fatal {
+                code,
+                message,
+                data,
+            }
+            | Error::Rpc {
+                code,
+                message,
+                data,
+            } => write!(f, "Error {code} - {message}: {data}"),
+            Error::Io(err) => write!(f, "{err}"),
+        }
     }
 }
 
 impl std::error::Error for Error {}
 
-pub struct Client<IO: StringIO> {
-    io: IO,
-    request_id_counter: i64,
+/// A command sent to the background reader, which is the sole owner of the
+/// underlying `IO` and therefore the only thing allowed to touch it.
+enum Command {
+    Send(String),
+    OnRequest(String, RequestHandler),
+    OnNotification(String, NotificationHandler),
 }
 
-impl<IO: StringIO> Client<IO> {
-    pub fn new(io: IO) -> Self {
-        Self {
+/// An LSP client that dispatches requests from any number of callers
+/// concurrently: a single background thread owns the `IO` and routes each
+/// incoming frame by id, so `request` no longer has to be the only thing
+/// reading the stream and callers don't serialize behind one another's
+/// round trips.
+#[derive(Clone)]
+pub struct Client {
+    commands: mpsc::Sender<Command>,
+    pending: Arc<Mutex<HashMap<RequestId, PendingRequest>>>,
+    notifications: Arc<Mutex<VecDeque<(String, Value)>>>,
+    progress_active: Arc<Mutex<HashSet<String>>>,
+    progress_ended: Arc<Mutex<HashSet<String>>>,
+    diagnostics: Arc<Mutex<HashMap<Url, PublishDiagnosticsParams>>>,
+    request_id_counter: Arc<AtomicI64>,
+}
+
+impl Client {
+    pub fn new(io: impl StringIO) -> Self {
+        let (commands_tx, commands_rx) = mpsc::channel();
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        let notifications = Arc::new(Mutex::new(VecDeque::new()));
+        let progress_active = Arc::new(Mutex::new(HashSet::new()));
+        let progress_ended = Arc::new(Mutex::new(HashSet::new()));
+        let diagnostics = Arc::new(Mutex::new(HashMap::new()));
+
+        spawn_reader(
             io,
-            request_id_counter: 0,
-        }
+            commands_rx,
+            pending.clone(),
+            notifications.clone(),
+            progress_active.clone(),
+            progress_ended.clone(),
+            diagnostics.clone(),
+        );
+
+        let client = Self {
+            commands: commands_tx,
+            pending,
+            notifications,
+            progress_active,
+            progress_ended,
+            diagnostics,
+            request_id_counter: Arc::new(AtomicI64::new(0)),
+        };
+
+        // Without a reply these block the server indefinitely, so ack them by
+        // default; callers that care can override with `on_request`.
+        client.on_request("client/registerCapability", |_| json!(null));
+        client.on_request("workspace/configuration", |params| {
+            let count = params
+                .get("items")
+                .and_then(Value::as_array)
+                .map_or(1, Vec::len);
+            json!(vec![Value::Null; count])
+        });
+
+        client
+    }
+
+    /// Registers a handler for a server-initiated request named `method`.
+    /// Replaces any handler previously registered for the same method.
+    pub fn on_request(&self, method: &str, mut handler: impl FnMut(Value) -> Value + Send + 'static) {
+        let _ = self.commands.send(Command::OnRequest(
+            method.to_string(),
+            Box::new(move |params| handler(params)),
+        ));
+    }
+
+    /// Registers a handler for a server-initiated notification named
+    /// `method`. Replaces any handler previously registered for the same
+    /// method.
+    pub fn on_notification(&self, method: &str, mut handler: impl FnMut(Value) + Send + 'static) {
+        let _ = self.commands.send(Command::OnNotification(
+            method.to_string(),
+            Box::new(move |params| handler(params)),
+        ));
+    }
+
+    /// Drains and returns every notification received since the last call
+    /// for which no handler was registered via [`Client::on_notification`]
+    /// -- e.g. `textDocument/publishDiagnostics` or `$/progress` for a
+    /// caller that would rather poll than install a callback.
+    pub fn poll_notifications(&self) -> Vec<(String, Value)> {
+        self.notifications
+            .lock()
+            .expect("notifications queue lock poisoned")
+            .drain(..)
+            .collect()
     }
 
-    pub fn request<R>(&mut self, params: Option<R::Params>) -> Result<R::Result>
+    /// Sends an LSP request and awaits its response, transparently retrying
+    /// (with backoff, up to [`MAX_TRANSIENT_RETRIES`] times) if the server
+    /// comes back with a transient error such as `ContentModified` -- the
+    /// retry a caller would otherwise have to hand-roll, e.g. around
+    /// `FoldingRangeRequest` while a server is still indexing.
+    pub fn request<R>(&self, params: Option<R::Params>) -> Result<R::Result>
     where
         R: LspRequest,
+        R::Params: Clone,
     {
-        let request = jsonrpc::Request {
-            jsonrpc: "2.0".to_string(),
-            method: R::METHOD.to_string(),
-            params,
-            id: self.request_id_counter,
-        };
+        let mut attempt = 0;
 
-        let msg = serde_json::to_string(&request).context("serializing request")?;
+        loop {
+            match self.request_once::<R>(params.clone()) {
+                Ok(result) => return Ok(result),
+                Err(err) => {
+                    let transient = err.downcast_ref::<Error>().is_some_and(Error::is_transient);
 
-        self.io
-            .send(&format!(
-                "Content-Length: {}\r\n\r\n{}",
-                msg.as_bytes().len(),
-                msg
-            ))
-            .context("sending request")?;
+                    if !transient || attempt >= MAX_TRANSIENT_RETRIES {
+                        return Err(err);
+                    }
 
-        eprintln!("\t\tSent: {}", msg);
+                    attempt += 1;
+                    thread::sleep(RETRY_BACKOFF * attempt);
+                }
+            }
+        }
+    }
 
-        let response = loop {
-            let response = self.io.recv().context("receiving response")?;
+    fn request_once<R>(&self, params: Option<R::Params>) -> Result<R::Result>
+    where
+        R: LspRequest,
+    {
+        let (_id, rx) = self.send_request::<R>(params)?;
 
-            eprintln!("\t\tReceived: {}", response);
+        // Cleaned up on both paths: the reader removes the entry once it
+        // delivers a value, and clears every entry when it stops (reader
+        // gone / IO died), which drops the sender so this `recv` fails
+        // instead of waiting for a response that can never arrive.
+        let response = rx
+            .recv()
+            .context("waiting for response")
+            .map_err(Error::Io)?;
 
-            let json_value: Value =
-                serde_json::from_str(&response).context("deserializing response")?;
+        Self::parse_response::<R>(response)
+    }
 
-            // check if this is our response
-            if let Some(id) = json_value.get("id").and_then(Value::as_i64) {
-                if id == self.request_id_counter {
-                    // this is a server sent method - not our response
-                    if json_value.get("method").is_some() {
-                        continue;
-                    }
+    /// Like [`Client::request_once`], but gives up and [`Client::cancel`]s
+    /// the request if no response arrives within `timeout` -- e.g. for a
+    /// `references` call a server might stall on, so one slow symbol doesn't
+    /// block every symbol after it.
+    pub fn request_with_timeout<R>(
+        &self,
+        params: Option<R::Params>,
+        timeout: Duration,
+    ) -> Result<R::Result>
+    where
+        R: LspRequest,
+    {
+        let (id, rx) = self.send_request::<R>(params)?;
 
-                    break response;
-                }
+        let response = match rx.recv_timeout(timeout) {
+            Ok(response) => response,
+            Err(_) => {
+                self.cancel(id)?;
+                anyhow::bail!("timed out waiting for {} response", R::METHOD);
             }
         };
 
-        self.request_id_counter += 1;
+        Self::parse_response::<R>(response)
+    }
+
+    /// Sends a single request and registers it in `pending`, returning its id
+    /// (for [`Client::cancel`]) and the channel its response is delivered on.
+    fn send_request<R>(&self, params: Option<R::Params>) -> Result<(RequestId, mpsc::Receiver<Value>)>
+    where
+        R: LspRequest,
+    {
+        let id = RequestId::Number(self.request_id_counter.fetch_add(1, Ordering::SeqCst));
 
+        let request = jsonrpc::Request {
+            jsonrpc: "2.0".to_string(),
+            method: R::METHOD.to_string(),
+            params,
+            id: id.clone(),
+        };
+        let msg = serde_json::to_string(&request)
+            .context("serializing request")
+            .map_err(Error::Io)?;
+
+        let (tx, rx) = mpsc::channel();
+        self.pending.lock().expect("pending responses lock poisoned").insert(
+            id.clone(),
+            PendingRequest {
+                method: R::METHOD.to_string(),
+                sender: tx,
+            },
+        );
+
+        if let Err(err) = self.send(msg) {
+            self.pending
+                .lock()
+                .expect("pending responses lock poisoned")
+                .remove(&id);
+            return Err(Error::Io(err).into());
+        }
+
+        Ok((id, rx))
+    }
+
+    fn parse_response<R: LspRequest>(response: Value) -> Result<R::Result> {
         let jsonrpc_response: jsonrpc::Response<R::Result, serde_json::Value> =
-            serde_json::from_str(&response).context("deserializing response")?;
+            serde_json::from_value(response)
+                .context("deserializing response")
+                .map_err(Error::Io)?;
 
         match jsonrpc_response.result {
             jsonrpc::JsonRpcResult::Result(result) => Ok(result),
@@ -92,16 +349,37 @@ impl<IO: StringIO> Client<IO> {
                 code,
                 message,
                 data,
-            } => Err((Error {
-                code,
-                message,
-                data,
-            })
-            .into()),
+            } => Err(Error::from_jsonrpc(code, message, data).into()),
         }
     }
 
-    pub fn notify<R>(&mut self, params: Option<R::Params>) -> Result<()>
+    /// Sends `$/cancelRequest` for `id` and removes it from the pending map,
+    /// so a response that arrives after this (if the server sends one
+    /// anyway) is discarded instead of being matched to a later request that
+    /// reused the id's slot.
+    pub fn cancel(&self, id: RequestId) -> Result<()> {
+        let removed = self
+            .pending
+            .lock()
+            .expect("pending responses lock poisoned")
+            .remove(&id);
+
+        if let Some(pending_request) = removed {
+            eprintln!("Cancelling {} request {}", pending_request.method, id);
+        }
+
+        let notification = jsonrpc::Notification {
+            jsonrpc: "2.0".to_string(),
+            method: "$/cancelRequest".to_string(),
+            params: Some(json!({ "id": id })),
+        };
+
+        let msg = serde_json::to_string(&notification).context("serializing cancel notification")?;
+
+        self.send(msg)
+    }
+
+    pub fn notify<R>(&self, params: Option<R::Params>) -> Result<()>
     where
         R: LspNotification,
     {
@@ -113,16 +391,227 @@ impl<IO: StringIO> Client<IO> {
 
         let msg = serde_json::to_string(&notification).context("serializing notification")?;
 
-        self.io
-            .send(&format!(
-                "Content-Length: {}\r\n\r\n{}",
-                msg.as_bytes().len(),
-                msg
-            ))
-            .context("sending notification")?;
+        self.send(msg)
+    }
+
+    /// Blocks until every `$/progress` token the server has started (e.g.
+    /// rust-analyzer's "Indexing") has reported `end`, instead of guessing a
+    /// fixed sleep. Waits for at least one token to begin and then drain, so
+    /// it won't return before progress has even started; `timeout` bounds
+    /// the wall-clock time spent, as a fallback for servers that never
+    /// report progress at all (this requires `window.workDoneProgress: true`
+    /// in `initialize`'s capabilities, without which a server can't report
+    /// it).
+    pub fn wait_for_progress_idle(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut progress_seen = false;
+
+        loop {
+            {
+                let active = self
+                    .progress_active
+                    .lock()
+                    .expect("progress active lock poisoned");
+                progress_seen = progress_seen
+                    || !active.is_empty()
+                    || !self
+                        .progress_ended
+                        .lock()
+                        .expect("progress ended lock poisoned")
+                        .is_empty();
+
+                if progress_seen && active.is_empty() {
+                    return Ok(());
+                }
+            }
+
+            if Instant::now() >= deadline {
+                eprintln!("Timed out waiting for progress to finish");
+                return Ok(());
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// The most recent `textDocument/publishDiagnostics` the server has sent
+    /// for each file, keyed by file URI. Empty for a file the server hasn't
+    /// reported on yet. Lets a caller like the graph builder skip or flag
+    /// files that failed to compile instead of trusting references computed
+    /// from a server that couldn't resolve them.
+    pub fn diagnostics(&self) -> HashMap<Url, PublishDiagnosticsParams> {
+        self.diagnostics
+            .lock()
+            .expect("diagnostics lock poisoned")
+            .clone()
+    }
 
-        eprintln!("\t\tSent: {}", msg);
+    fn send(&self, msg: String) -> Result<()> {
+        let framed = format!("Content-Length: {}\r\n\r\n{}", msg.as_bytes().len(), msg);
 
-        Ok(())
+        self.commands
+            .send(Command::Send(framed))
+            .context("sending message to reader thread")
     }
 }
+
+/// Records a `textDocument/publishDiagnostics` notification, replacing
+/// whatever was previously known about that file -- servers resend the full
+/// set each time, not a diff. Skipped if `version` is older than the version
+/// already on file, so a late, stale publish can't clobber fresher results.
+fn record_diagnostics(
+    diagnostics: &Mutex<HashMap<Url, PublishDiagnosticsParams>>,
+    params: &Value,
+) {
+    let Ok(params) = serde_json::from_value::<PublishDiagnosticsParams>(params.clone()) else {
+        return;
+    };
+
+    let mut diagnostics = diagnostics.lock().expect("diagnostics lock poisoned");
+    let is_stale = diagnostics
+        .get(&params.uri)
+        .is_some_and(|existing| matches!((existing.version, params.version), (Some(existing), Some(new)) if new < existing));
+
+    if !is_stale {
+        diagnostics.insert(params.uri.clone(), params);
+    }
+}
+
+/// Spawns the single thread that owns `io`: it drains queued outgoing
+/// commands, reads the next frame, and routes it -- a response goes to the
+/// matching pending sender by id, a server-initiated request goes through
+/// the request handler registry (replying with a `MethodNotFound` error so
+/// the server isn't left hanging if none is registered), and a notification
+/// goes through its handler if one is registered or onto `notifications`
+/// for [`Client::poll_notifications`] otherwise.
+fn spawn_reader(
+    mut io: impl StringIO,
+    commands: mpsc::Receiver<Command>,
+    pending: Arc<Mutex<HashMap<RequestId, PendingRequest>>>,
+    notifications: Arc<Mutex<VecDeque<(String, Value)>>>,
+    progress_active: Arc<Mutex<HashSet<String>>>,
+    progress_ended: Arc<Mutex<HashSet<String>>>,
+    diagnostics: Arc<Mutex<HashMap<Url, PublishDiagnosticsParams>>>,
+) {
+    thread::spawn(move || {
+        let mut request_handlers: HashMap<String, RequestHandler> = HashMap::new();
+        let mut notification_handlers: HashMap<String, NotificationHandler> = HashMap::new();
+
+        'reader: loop {
+            for command in commands.try_iter() {
+                match command {
+                    Command::Send(frame) => {
+                        if io.send(&frame).is_err() {
+                            break 'reader;
+                        }
+                    }
+                    Command::OnRequest(method, handler) => {
+                        request_handlers.insert(method, handler);
+                    }
+                    Command::OnNotification(method, handler) => {
+                        notification_handlers.insert(method, handler);
+                    }
+                }
+            }
+
+            let frame = match io.recv() {
+                Ok(frame) => frame,
+                Err(_) => break 'reader,
+            };
+
+            let message: ServerMessage = match serde_json::from_str(&frame) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+
+            match message {
+                ServerMessage::Response(_) => {
+                    let Ok(value) = serde_json::from_str::<Value>(&frame) else {
+                        continue;
+                    };
+                    let Some(id) = value
+                        .get("id")
+                        .filter(|id| !id.is_null())
+                        .and_then(|id| serde_json::from_value::<RequestId>(id.clone()).ok())
+                    else {
+                        continue;
+                    };
+                    if let Some(pending_request) = pending.lock().expect("pending responses lock poisoned").remove(&id) {
+                        let _ = pending_request.sender.send(value);
+                    }
+                }
+                ServerMessage::Request(request) => {
+                    let response = match request_handlers.get_mut(&request.method) {
+                        Some(handler) => json!({
+                            "jsonrpc": "2.0",
+                            "id": request.id,
+                            "result": handler(request.params),
+                        }),
+                        None => json!({
+                            "jsonrpc": "2.0",
+                            "id": request.id,
+                            "error": {
+                                "code": -32601,
+                                "message": format!("no handler registered for {}", request.method),
+                            },
+                        }),
+                    };
+
+                    if let Ok(response) = serde_json::to_string(&response) {
+                        if io.send(&response).is_err() {
+                            break 'reader;
+                        }
+                    }
+                }
+                ServerMessage::Notification(notification) => {
+                    if notification.method == "textDocument/publishDiagnostics" {
+                        record_diagnostics(&diagnostics, &notification.params);
+                    }
+
+                    if notification.method == "$/progress" {
+                        let token = notification.params.get("token").and_then(Value::as_str);
+                        let kind = notification
+                            .params
+                            .get("value")
+                            .and_then(|value| value.get("kind"))
+                            .and_then(Value::as_str);
+
+                        if let Some(token) = token {
+                            match kind {
+                                Some("begin") => {
+                                    progress_active
+                                        .lock()
+                                        .expect("progress active lock poisoned")
+                                        .insert(token.to_string());
+                                }
+                                Some("end") => {
+                                    progress_active
+                                        .lock()
+                                        .expect("progress active lock poisoned")
+                                        .remove(token);
+                                    progress_ended
+                                        .lock()
+                                        .expect("progress ended lock poisoned")
+                                        .insert(token.to_string());
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+
+                    match notification_handlers.get_mut(&notification.method) {
+                        Some(handler) => handler(notification.params),
+                        None => notifications
+                            .lock()
+                            .expect("notifications queue lock poisoned")
+                            .push_back((notification.method, notification.params)),
+                    }
+                }
+            }
+        }
+
+        // Drop every pending sender so callers blocked on a response get an
+        // error instead of waiting on a reader that has stopped.
+        pending.lock().expect("pending responses lock poisoned").clear();
+    });
+}
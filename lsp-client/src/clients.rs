@@ -0,0 +1,565 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use lsp_types::{
+    notification::Notification as LspNotification, request::Request as LspRequest, ProgressToken,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::process::{ChildStderr, ChildStdin, ChildStdout};
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::task::JoinHandle;
+
+use crate::jsonrpc::{self, RequestId};
+use crate::transport::{read_message, StdioTransport, TcpTransport, Transport};
+
+/// Receives each partial-result array a server delivers through `$/progress`
+/// for a streaming request, in arrival order.
+type ProgressSink = Box<dyn FnMut(Value) + Send>;
+
+/// Handles a server-initiated request for `method`, returning the `result`
+/// value to send back. Without a reply these block the server indefinitely.
+type RequestHandler = Box<dyn FnMut(Value) -> Value + Send>;
+
+fn progress_token_key(token: &ProgressToken) -> String {
+    match token {
+        ProgressToken::Number(n) => n.to_string(),
+        ProgressToken::String(s) => s.clone(),
+    }
+}
+
+/// An async LSP client backed by a single task that owns the wire and
+/// multiplexes concurrent requests by id. Replaces the polling-thread
+/// `ChildStdioChannel`: shutdown is a dropped channel rather than an
+/// `AtomicBool` a reader has to wake up and notice.
+#[derive(Clone)]
+pub struct Client {
+    outgoing: mpsc::UnboundedSender<String>,
+    pending: Arc<Mutex<HashMap<RequestId, oneshot::Sender<Value>>>>,
+    progress_sinks: Arc<Mutex<HashMap<String, ProgressSink>>>,
+    progress_active: Arc<Mutex<HashSet<String>>>,
+    progress_ended: Arc<Mutex<HashSet<String>>>,
+    progress_notify: Arc<Notify>,
+    request_handlers: Arc<Mutex<HashMap<String, RequestHandler>>>,
+    request_id_counter: Arc<AtomicI64>,
+}
+
+impl Client {
+    /// Registers a handler for a server-initiated request named `method`.
+    /// Replaces any handler previously registered for the same method.
+    pub fn on_request(&self, method: &str, mut handler: impl FnMut(Value) -> Value + Send + 'static) {
+        self.request_handlers
+            .lock()
+            .expect("request handlers lock poisoned")
+            .insert(method.to_string(), Box::new(move |params| handler(params)));
+    }
+
+    pub async fn request<R, E>(&self, params: R::Params) -> Result<jsonrpc::Response<R::Result, E>>
+    where
+        R: LspRequest,
+        E: DeserializeOwned,
+    {
+        let id = RequestId::Number(self.request_id_counter.fetch_add(1, Ordering::SeqCst));
+        let (tx, rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .expect("pending responses lock poisoned")
+            .insert(id.clone(), tx);
+
+        let request = jsonrpc::Request {
+            jsonrpc: "2.0".to_string(),
+            method: R::METHOD.to_string(),
+            params: Some(params),
+            id: id.clone(),
+        };
+
+        if let Err(err) = self.send(&request) {
+            self.pending.lock().expect("pending responses lock poisoned").remove(&id);
+            return Err(err);
+        }
+
+        let value = rx.await.context("waiting for response")?;
+        serde_json::from_value(value).context("deserializing response")
+    }
+
+    /// Mints a progress token unique to this client, to pass as a request's
+    /// `partial_result_token`/`work_done_token` before calling
+    /// [`Client::request_streaming`].
+    pub fn mint_progress_token(&self) -> ProgressToken {
+        ProgressToken::Number(self.request_id_counter.fetch_add(1, Ordering::SeqCst))
+    }
+
+    /// Like [`Client::request`], but also collects the partial result arrays
+    /// the server streams back via `$/progress` notifications carrying
+    /// `token` (the caller must have put `token` in the request's
+    /// `partial_result_params`/`work_done_progress_params`), calling
+    /// `on_partial` with each one as it arrives. Registration is cleaned up
+    /// once the final response comes back, whether or not it errored.
+    pub async fn request_streaming<R, E>(
+        &self,
+        params: R::Params,
+        token: ProgressToken,
+        mut on_partial: impl FnMut(Value) + Send + 'static,
+    ) -> Result<jsonrpc::Response<R::Result, E>>
+    where
+        R: LspRequest,
+        E: DeserializeOwned,
+    {
+        let key = progress_token_key(&token);
+        self.progress_sinks
+            .lock()
+            .expect("progress sinks lock poisoned")
+            .insert(key.clone(), Box::new(move |value| on_partial(value)));
+
+        let result = self.request::<R, E>(params).await;
+
+        self.progress_sinks
+            .lock()
+            .expect("progress sinks lock poisoned")
+            .remove(&key);
+
+        result
+    }
+
+    /// Waits for the `$/progress` work the server starts after `initialize`
+    /// (e.g. rust-analyzer's "Indexing") to report `end`, instead of guessing
+    /// a fixed sleep. Waits for at least one token to begin and then drain,
+    /// so it won't return before indexing has even started; `timeout` bounds
+    /// the wall-clock time spent, as a fallback for servers that never
+    /// report progress at all (or weren't sent `window.workDoneProgress:
+    /// true` in `initialize`'s capabilities, without which they can't report
+    /// it).
+    pub async fn wait_until_ready(&self, timeout: Duration) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut progress_seen = false;
+
+        loop {
+            {
+                let active = self
+                    .progress_active
+                    .lock()
+                    .expect("progress active lock poisoned");
+                progress_seen = progress_seen
+                    || !active.is_empty()
+                    || !self
+                        .progress_ended
+                        .lock()
+                        .expect("progress ended lock poisoned")
+                        .is_empty();
+
+                if progress_seen && active.is_empty() {
+                    return Ok(());
+                }
+            }
+
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if remaining.is_zero() {
+                eprintln!("Timed out waiting for indexing to finish");
+                return Ok(());
+            }
+
+            let _ = tokio::time::timeout(remaining, self.progress_notify.notified()).await;
+        }
+    }
+
+    pub fn notify<N: LspNotification>(&self, params: N::Params) -> Result<()> {
+        let notification = jsonrpc::Notification {
+            jsonrpc: "2.0".to_string(),
+            method: N::METHOD.to_string(),
+            params: Some(params),
+        };
+
+        self.send(&notification)
+    }
+
+    fn send(&self, msg: &impl Serialize) -> Result<()> {
+        let msg = serde_json::to_string(msg).context("serializing message")?;
+        let framed = format!("Content-Length: {}\r\n\r\n{}", msg.as_bytes().len(), msg);
+        self.outgoing
+            .send(framed)
+            .context("sending message to writer task")
+    }
+
+    /// Serializes an LSP request and registers it for a pending response
+    /// without sending it. Pair several of these with [`Client::batch`] to
+    /// fire them as a single JSON-RPC batch instead of N sequential awaits.
+    pub fn prepare_request<R: LspRequest>(&self, params: R::Params) -> Result<BatchEntry> {
+        let id = RequestId::Number(self.request_id_counter.fetch_add(1, Ordering::SeqCst));
+        let (tx, rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .expect("pending responses lock poisoned")
+            .insert(id.clone(), tx);
+
+        let request = jsonrpc::Request {
+            jsonrpc: "2.0".to_string(),
+            method: R::METHOD.to_string(),
+            params: Some(params),
+            id: id.clone(),
+        };
+        let frame = serde_json::to_value(&request).context("serializing batched request")?;
+
+        Ok(BatchEntry { id, frame, rx })
+    }
+
+    /// Sends a set of [`BatchEntry`]s as a single JSON-RPC batch array and
+    /// awaits every response, matched back to its originating call by id --
+    /// batch replies can arrive in any order. A server that rejects the whole
+    /// batch sends back a single top-level response with `id: null`; when
+    /// that happens every entry's slot resolves to that same error instead of
+    /// hanging forever.
+    pub async fn batch(&self, entries: Vec<BatchEntry>) -> Result<Vec<Result<Value>>> {
+        let ids: Vec<RequestId> = entries.iter().map(|entry| entry.id.clone()).collect();
+        let frames: Vec<Value> = entries.iter().map(|entry| entry.frame.clone()).collect();
+        let receivers: Vec<_> = entries.into_iter().map(|entry| entry.rx).collect();
+
+        let msg = serde_json::to_string(&frames).context("serializing batch")?;
+        let framed = format!("Content-Length: {}\r\n\r\n{}", msg.as_bytes().len(), msg);
+
+        if let Err(err) = self.outgoing.send(framed) {
+            let mut pending = self.pending.lock().expect("pending responses lock poisoned");
+            for id in &ids {
+                pending.remove(id);
+            }
+            return Err(err).context("sending batch to writer task");
+        }
+
+        let mut results = Vec::with_capacity(ids.len());
+        for (id, rx) in ids.into_iter().zip(receivers) {
+            results.push(
+                rx.await
+                    .with_context(|| format!("waiting for batched response id {id}")),
+            );
+        }
+
+        Ok(results)
+    }
+}
+
+/// One call queued for a [`Client::batch`] round trip; create with
+/// [`Client::prepare_request`].
+pub struct BatchEntry {
+    id: RequestId,
+    frame: Value,
+    rx: oneshot::Receiver<Value>,
+}
+
+/// Dispatches a single top-level JSON-RPC message -- either the whole frame
+/// the server sent, or one element of a batch-reply array -- routing it the
+/// same way regardless of which: a reply to one of our requests goes to its
+/// matching pending sender by id, a server-initiated request is answered via
+/// the handler registry, `$/progress` updates indexing state and any
+/// registered streaming sink, and a batch-rejection error (`id: null`) is
+/// flushed to every in-flight waiter so [`Client::batch`] doesn't hang.
+fn dispatch_message(
+    value: Value,
+    pending: &Arc<Mutex<HashMap<RequestId, oneshot::Sender<Value>>>>,
+    progress_sinks: &Arc<Mutex<HashMap<String, ProgressSink>>>,
+    progress_active: &Arc<Mutex<HashSet<String>>>,
+    progress_ended: &Arc<Mutex<HashSet<String>>>,
+    progress_notify: &Arc<Notify>,
+    request_handlers: &Arc<Mutex<HashMap<String, RequestHandler>>>,
+    outgoing: &mpsc::UnboundedSender<String>,
+) {
+    // A message with no `id` is a server notification -- except a
+    // batch-rejection error, which carries `id: null` and must be flushed to
+    // every in-flight waiter or `Client::batch` hangs forever -- and
+    // `$/progress`, which carries a partial result for a `request_streaming`
+    // caller to pick up.
+    let Some(id) = value
+        .get("id")
+        .filter(|id| !id.is_null())
+        .and_then(|id| serde_json::from_value::<RequestId>(id.clone()).ok())
+    else {
+        if value.get("method").and_then(Value::as_str) == Some("$/progress") {
+            if let Some(params) = value.get("params") {
+                if let (Some(token), Some(partial)) = (params.get("token"), params.get("value")) {
+                    let key = token
+                        .as_str()
+                        .map(str::to_string)
+                        .or_else(|| token.as_i64().map(|n| n.to_string()));
+                    if let Some(key) = key {
+                        match partial.get("kind").and_then(Value::as_str) {
+                            Some("begin") => {
+                                progress_active
+                                    .lock()
+                                    .expect("progress active lock poisoned")
+                                    .insert(key.clone());
+                                progress_notify.notify_one();
+                            }
+                            Some("end") => {
+                                progress_active
+                                    .lock()
+                                    .expect("progress active lock poisoned")
+                                    .remove(&key);
+                                progress_ended
+                                    .lock()
+                                    .expect("progress ended lock poisoned")
+                                    .insert(key.clone());
+                                progress_notify.notify_one();
+                            }
+                            _ => {}
+                        }
+
+                        if let Some(sink) = progress_sinks
+                            .lock()
+                            .expect("progress sinks lock poisoned")
+                            .get_mut(&key)
+                        {
+                            sink(partial.clone());
+                        }
+                    }
+                }
+            }
+        } else if value.get("error").is_some() {
+            let mut pending = pending.lock().expect("pending responses lock poisoned");
+            for (_, tx) in pending.drain() {
+                let _ = tx.send(value.clone());
+            }
+        }
+        return;
+    };
+
+    // An `id` with a `method` is a server-initiated request (e.g.
+    // `client/registerCapability`), not a reply to anything we sent --
+    // answer it via the handler registry so the server isn't left waiting
+    // forever, defaulting to a `MethodNotFound` error if nothing is
+    // registered for it.
+    if let Some(method) = value.get("method").and_then(Value::as_str) {
+        let params = value.get("params").cloned().unwrap_or(Value::Null);
+        let result = request_handlers
+            .lock()
+            .expect("request handlers lock poisoned")
+            .get_mut(method)
+            .map(|handler| handler(params));
+
+        let response = match result {
+            Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            None => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": -32601, "message": format!("no handler registered for {}", method) },
+            }),
+        };
+
+        if let Ok(response) = serde_json::to_string(&response) {
+            let framed = format!("Content-Length: {}\r\n\r\n{}", response.as_bytes().len(), response);
+            let _ = outgoing.send(framed);
+        }
+
+        return;
+    }
+
+    if let Some(tx) = pending.lock().expect("pending responses lock poisoned").remove(&id) {
+        let _ = tx.send(value);
+    }
+}
+
+/// Spawns the reader/writer tasks for any [`Transport`] and returns a
+/// [`Client`] plus the task handles (abort these, or let them finish on their
+/// own, when the server shuts down).
+pub fn connect<T: Transport>(transport: T) -> (Client, Vec<JoinHandle<()>>) {
+    let (read, write) = transport.split();
+
+    let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel::<String>();
+    let pending = Arc::new(Mutex::new(HashMap::new()));
+    let progress_sinks: Arc<Mutex<HashMap<String, ProgressSink>>> = Arc::new(Mutex::new(HashMap::new()));
+
+    let progress_active: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+    let progress_ended: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+    let progress_notify = Arc::new(Notify::new());
+
+    let request_handlers: Arc<Mutex<HashMap<String, RequestHandler>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+    {
+        let mut handlers = request_handlers.lock().expect("request handlers lock poisoned");
+        // Without a reply these block the server indefinitely, so ack them by
+        // default; callers that care can override with `Client::on_request`.
+        handlers.insert(
+            "client/registerCapability".to_string(),
+            Box::new(|_| json!(null)),
+        );
+        handlers.insert(
+            "workspace/configuration".to_string(),
+            Box::new(|params| {
+                let count = params
+                    .get("items")
+                    .and_then(Value::as_array)
+                    .map_or(1, Vec::len);
+                json!(vec![Value::Null; count])
+            }),
+        );
+    }
+
+    let pending_reader = pending.clone();
+    let progress_sinks_reader = progress_sinks.clone();
+    let progress_active_reader = progress_active.clone();
+    let progress_ended_reader = progress_ended.clone();
+    let progress_notify_reader = progress_notify.clone();
+    let request_handlers_reader = request_handlers.clone();
+    let outgoing_reader = outgoing_tx.clone();
+    let reader_handle = tokio::spawn(async move {
+        let mut reader = read;
+
+        loop {
+            let frame = match read_message(&mut reader).await {
+                Ok(frame) => frame,
+                Err(err) => {
+                    eprintln!("stopping reader, server stream ended: {}", err);
+                    break;
+                }
+            };
+
+            let Ok(value) = serde_json::from_str::<Value>(&frame) else {
+                eprintln!("dropping unparseable frame: {}", frame);
+                continue;
+            };
+
+            // A batch reply is a top-level JSON array of per-call response
+            // objects (order not guaranteed); dispatch each one as if it had
+            // arrived on its own.
+            let messages = match value {
+                Value::Array(values) => values,
+                value => vec![value],
+            };
+
+            for message in messages {
+                dispatch_message(
+                    message,
+                    &pending_reader,
+                    &progress_sinks_reader,
+                    &progress_active_reader,
+                    &progress_ended_reader,
+                    &progress_notify_reader,
+                    &request_handlers_reader,
+                    &outgoing_reader,
+                );
+            }
+        }
+    });
+
+    let writer_handle = tokio::spawn(async move {
+        let mut writer = BufWriter::new(write);
+
+        while let Some(msg) = outgoing_rx.recv().await {
+            if writer.write_all(msg.as_bytes()).await.is_err() || writer.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let client = Client {
+        outgoing: outgoing_tx,
+        pending,
+        progress_sinks,
+        progress_active,
+        progress_ended,
+        progress_notify,
+        request_handlers,
+        request_id_counter: Arc::new(AtomicI64::new(0)),
+    };
+
+    (client, vec![reader_handle, writer_handle])
+}
+
+/// Spawns a language server's stdio as LSP base-protocol framing, draining
+/// its stderr to a log line per line, and returns a [`Client`] plus the task
+/// handles (abort these, or let them finish on their own, when the server
+/// shuts down).
+pub fn stdio_client(
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+) -> (Client, Vec<JoinHandle<()>>) {
+    let (client, mut handles) = connect(StdioTransport { stdin, stdout });
+
+    handles.push(tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            eprintln!("stderr: {}", line);
+        }
+    }));
+
+    (client, handles)
+}
+
+/// Connects to a language server listening on `host:port` instead of being
+/// spawned directly, using the same `Content-Length` framing as
+/// [`stdio_client`].
+pub async fn tcp_client(addr: &str) -> Result<(Client, Vec<JoinHandle<()>>)> {
+    let transport = TcpTransport::connect(addr)
+        .await
+        .with_context(|| format!("connecting to tcp transport at {addr}"))?;
+
+    Ok(connect(transport))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::request::Shutdown;
+    use tokio::io::{split, ReadHalf, WriteHalf};
+
+    /// Wraps one end of an in-memory duplex pipe as a [`Transport`], so a
+    /// test can sit on the other end and play server without spawning a real
+    /// process.
+    struct DuplexTransport(tokio::io::DuplexStream);
+
+    impl Transport for DuplexTransport {
+        type Read = BufReader<ReadHalf<tokio::io::DuplexStream>>;
+        type Write = WriteHalf<tokio::io::DuplexStream>;
+
+        fn split(self) -> (Self::Read, Self::Write) {
+            let (read, write) = split(self.0);
+            (BufReader::new(read), write)
+        }
+    }
+
+    /// A server reply to a `Client::batch` call is a single top-level JSON
+    /// array of per-call response objects, not one frame per call -- and the
+    /// spec allows them back in any order. The reader must split that array
+    /// and resolve each entry by id, rather than looking for an `id` on the
+    /// array itself (which has none) and dropping the whole reply.
+    #[tokio::test]
+    async fn test_batch_reply_array_resolves_every_entry() {
+        let (client_side, server_side) = tokio::io::duplex(8192);
+        let (client, _handles) = connect(DuplexTransport(client_side));
+        let (mut server_read, mut server_write) = split(server_side);
+        let mut server_read = BufReader::new(&mut server_read);
+
+        let a = client.prepare_request::<Shutdown>(()).unwrap();
+        let b = client.prepare_request::<Shutdown>(()).unwrap();
+        let (a_id, b_id) = (a.id.clone(), b.id.clone());
+
+        let batch = tokio::spawn(async move { client.batch(vec![a, b]).await.unwrap() });
+
+        let frame = read_message(&mut server_read).await.unwrap();
+        let requests: Vec<Value> = serde_json::from_str(&frame).unwrap();
+        assert_eq!(requests.len(), 2);
+
+        // Reply out of order, as the spec allows.
+        let response = serde_json::to_string(&json!([
+            { "jsonrpc": "2.0", "id": b_id, "result": null },
+            { "jsonrpc": "2.0", "id": a_id, "result": null },
+        ]))
+        .unwrap();
+        let framed = format!("Content-Length: {}\r\n\r\n{}", response.as_bytes().len(), response);
+        server_write.write_all(framed.as_bytes()).await.unwrap();
+        server_write.flush().await.unwrap();
+
+        let results = tokio::time::timeout(Duration::from_secs(5), batch)
+            .await
+            .expect("batch() hung instead of resolving both entries")
+            .expect("batch task panicked");
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(Result::is_ok));
+    }
+}
@@ -1,4 +1,32 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A JSON-RPC id: the spec allows either a number or a string, and peers
+/// that aren't our own LSP servers (or that proxy through something that
+/// reassigns ids) may send string ones. Carrying this instead of a bare
+/// `i64` lets ids be compared structurally rather than coerced through
+/// `as_i64`, which silently treats a string id the same as "no id at all".
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum RequestId {
+    Number(i64),
+    String(String),
+}
+
+impl From<i64> for RequestId {
+    fn from(id: i64) -> Self {
+        RequestId::Number(id)
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestId::Number(id) => write!(f, "{id}"),
+            RequestId::String(id) => write!(f, "{id}"),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Request<Params> {
@@ -6,7 +34,7 @@ pub struct Request<Params> {
     pub method: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub params: Option<Params>,
-    pub id: i64,
+    pub id: RequestId,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -21,7 +49,7 @@ pub struct Response<T, E> {
     pub jsonrpc: String,
     #[serde(flatten)]
     pub result: JsonRpcResult<T, E>,
-    pub id: Option<i64>,
+    pub id: Option<RequestId>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -35,6 +63,39 @@ pub enum JsonRpcResult<T, E> {
     },
 }
 
+/// A frame received off the server's stdout, classified by shape rather than
+/// by a tag in the payload (the base protocol doesn't have one): a reply to
+/// one of our requests, a request the server wants *us* to answer, or a
+/// one-way notification.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ServerMessage {
+    Response(Response<Value, Value>),
+    Request(ServerRequest),
+    Notification(ServerNotification),
+}
+
+/// A server-initiated request, e.g. `client/registerCapability` or
+/// `workspace/configuration`. Requires a `Response` to be sent back carrying
+/// the same `id`.
+#[derive(Deserialize, Debug)]
+pub struct ServerRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    pub id: RequestId,
+}
+
+/// A server-initiated notification, e.g. `window/logMessage` or `$/progress`.
+#[derive(Deserialize, Debug)]
+pub struct ServerNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -46,7 +107,7 @@ mod tests {
                 jsonrpc: "2.0".to_string(),
                 method: "method".to_string(),
                 params: Some(vec![42, 23]),
-                id: 1,
+                id: RequestId::Number(1),
             },
             @r###"{"jsonrpc": "2.0", "method": "method", "params": [42, 23], "id": 1}"###
         );
@@ -59,10 +120,20 @@ mod tests {
                 jsonrpc: "2.0".to_string(),
                 method: "method".to_string(),
                 params: Some(()),
-                id: 1,
+                id: RequestId::Number(1),
             },
             @r###"{"jsonrpc": "2.0", "method": "method", "params": null, "id": 1}"###
         );
+
+        insta::assert_compact_json_snapshot!(
+            Request {
+                jsonrpc: "2.0".to_string(),
+                method: "method".to_string(),
+                params: Some(vec![42, 23]),
+                id: RequestId::String("request-1".to_string()),
+            },
+            @r###"{"jsonrpc": "2.0", "method": "method", "params": [42, 23], "id": "request-1"}"###
+        );
     }
 
     #[test]
@@ -80,7 +151,30 @@ mod tests {
                         23,
                     ],
                 ),
-                id: 1,
+                id: Number(
+                    1,
+                ),
+            },
+        )
+        "###
+        );
+
+        insta::assert_debug_snapshot!(
+            serde_json::from_str::<Request<Vec<i32>>>(r#"{"jsonrpc": "2.0", "method": "method", "params": [42, 23], "id": "request-1"}"#),
+            @r###"
+        Ok(
+            Request {
+                jsonrpc: "2.0",
+                method: "method",
+                params: Some(
+                    [
+                        42,
+                        23,
+                    ],
+                ),
+                id: String(
+                    "request-1",
+                ),
             },
         )
         "###
@@ -126,7 +220,7 @@ mod tests {
             Response {
                 jsonrpc: "2.0".to_string(),
                 result: JsonRpcResult::<_, ()>::Result(19),
-                id: Some(1),
+                id: Some(RequestId::Number(1)),
             },
             @r###"{"jsonrpc": "2.0", "result": 19, "id": 1}"###
         );
@@ -157,7 +251,9 @@ mod tests {
                     19,
                 ),
                 id: Some(
-                    1,
+                    Number(
+                        1,
+                    ),
                 ),
             },
         )
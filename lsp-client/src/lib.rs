@@ -1,7 +1,11 @@
+mod child_stdio_channel;
 mod client;
+pub mod clients;
 mod jsonrpc;
 mod stdio;
+mod transport;
 
+pub use child_stdio_channel::ChildStdioChannel;
 pub use client::{Client, Error};
 pub use lsp_types as types;
 pub use stdio::StdIO;
@@ -1,7 +1,7 @@
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::process::{Child, ChildStdin, ChildStdout};
 
-use anyhow::Context;
+use anyhow::{Context, Result};
 
 pub struct StdIO {
     stdin: ChildStdin,
@@ -16,45 +16,70 @@ impl StdIO {
     }
 }
 
+/// The server closed its stdout before a full message could be read --
+/// distinct from a parse/IO error so callers can tell "server exited" apart
+/// from "sent us garbage" by downcasting the returned error.
+#[derive(Debug)]
+pub struct ConnectionClosed;
+
+impl std::fmt::Display for ConnectionClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "connection closed before a full message was read")
+    }
+}
+
+impl std::error::Error for ConnectionClosed {}
+
 impl crate::client::StringIO for StdIO {
-    fn send(&mut self, msg: &str) -> anyhow::Result<()> {
+    fn send(&mut self, msg: &str) -> Result<()> {
         self.stdin
             .write_all(msg.as_bytes())
             .context("writing msg to stdin")
     }
 
-    fn recv(&mut self) -> anyhow::Result<String> {
+    /// Reads header lines (tolerating unknown ones like `Content-Type`,
+    /// matched case-insensitively) until the blank separator line, then reads
+    /// *exactly* `Content-Length` bytes with `read_exact` rather than
+    /// scanning for a closing `}`, which breaks on `}` inside strings/nested
+    /// objects and on multi-byte UTF-8 boundaries.
+    fn recv(&mut self) -> Result<String> {
         let mut content_length = None;
-        let mut content_type = None;
 
         loop {
             let mut line = String::new();
-            self.stdout
+            let bytes_read = self
+                .stdout
                 .read_line(&mut line)
-                .context("reading line from stdout")?;
-            let words = line.split_ascii_whitespace().collect::<Vec<_>>();
-
-            match (words.as_slice(), &mut content_length, &mut content_type) {
-                (["Content-Length:", c_length], None, None) => {
-                    content_length = Some(c_length.parse().context("parsing Content-Length")?)
-                }
-                (["Content-Type:", c_type], Some(_), None) => {
-                    content_type = Some(c_type.to_string())
-                }
-                ([], Some(content_length), _) => {
-                    let mut content = Vec::with_capacity(*content_length);
-                    let mut bytes_left = *content_length;
-                    while bytes_left > 0 {
-                        let read_bytes = self.stdout.read_until(b'}', &mut content).unwrap();
-                        bytes_left -= read_bytes;
-                    }
-
-                    let content = String::from_utf8(content).unwrap();
-                    return Ok(content);
-                }
-                ([], None, None) => panic!("Unexpected server shut down"),
-                unexpected => panic!("Got unexpected stdout: {:?}", unexpected),
+                .context("reading header line from stdout")?;
+            if bytes_read == 0 {
+                return Err(ConnectionClosed.into());
+            }
+
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                break;
+            }
+
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
             };
+
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = Some(value.trim().parse().context("parsing Content-Length")?);
+            }
+            // Any other header (e.g. Content-Type) is accepted and ignored.
         }
+
+        let content_length = content_length.context("missing Content-Length")?;
+        let mut content = vec![0u8; content_length];
+        self.stdout.read_exact(&mut content).map_err(|err| {
+            if err.kind() == std::io::ErrorKind::UnexpectedEof {
+                anyhow::Error::new(ConnectionClosed)
+            } else {
+                anyhow::Error::new(err).context("reading message body")
+            }
+        })?;
+
+        String::from_utf8(content).context("decoding message body as utf-8")
     }
 }
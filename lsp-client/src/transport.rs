@@ -0,0 +1,101 @@
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::process::{ChildStdin, ChildStdout};
+
+/// Where a [`crate::clients::Client`] reads/writes framed LSP messages:
+/// a spawned server's stdio, or a socket for servers that listen on a port
+/// instead of being launched directly.
+pub trait Transport: Send + 'static {
+    type Read: AsyncBufRead + Unpin + Send + 'static;
+    type Write: AsyncWrite + Unpin + Send + 'static;
+
+    fn split(self) -> (Self::Read, Self::Write);
+}
+
+/// A spawned child's stdin/stdout, framed as LSP base-protocol messages.
+pub struct StdioTransport {
+    pub stdin: ChildStdin,
+    pub stdout: ChildStdout,
+}
+
+impl Transport for StdioTransport {
+    type Read = BufReader<ChildStdout>;
+    type Write = ChildStdin;
+
+    fn split(self) -> (Self::Read, Self::Write) {
+        (BufReader::new(self.stdout), self.stdin)
+    }
+}
+
+/// A TCP connection to a language server that listens on a port instead of
+/// being spawned as a child process, e.g. `tcp://127.0.0.1:9257`.
+pub struct TcpTransport(TcpStream);
+
+impl TcpTransport {
+    pub async fn connect(addr: &str) -> std::io::Result<Self> {
+        Ok(Self(TcpStream::connect(addr).await?))
+    }
+}
+
+impl Transport for TcpTransport {
+    type Read = BufReader<OwnedReadHalf>;
+    type Write = OwnedWriteHalf;
+
+    fn split(self) -> (Self::Read, Self::Write) {
+        let (read, write) = self.0.into_split();
+        (BufReader::new(read), write)
+    }
+}
+
+/// Reads one LSP base-protocol message off an async byte stream: a block of
+/// `\r\n`-terminated headers (only `Content-Length` is required,
+/// `Content-Type` is accepted and ignored) followed by the blank separator
+/// line, followed by exactly `Content-Length` bytes. Mirrors the sync framing
+/// in `child_stdio_channel`, just driven by `tokio::select!`-friendly reads.
+pub async fn read_message(rx: &mut (impl AsyncBufRead + Unpin)) -> std::io::Result<String> {
+    let mut content_length = None;
+
+    loop {
+        let mut line = String::new();
+        let bytes_read = rx.read_line(&mut line).await?;
+        if bytes_read == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "server closed the connection",
+            ));
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("malformed header: {:?}", line),
+            ));
+        };
+
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(value.trim().parse::<usize>().map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("malformed Content-Length: {:?}", value),
+                )
+            })?);
+        }
+        // Content-Type and any other headers are accepted and ignored.
+    }
+
+    let content_length = content_length.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "missing Content-Length")
+    })?;
+
+    let mut content = vec![0u8; content_length];
+    rx.read_exact(&mut content).await?;
+
+    String::from_utf8(content)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
@@ -1,27 +1,233 @@
-use std::io::{BufRead, Write};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use lsp_types::{notification::Notification, request::Request};
+use lsp_types::{
+    notification::{Exit, Notification},
+    request::{Request, Shutdown},
+    PublishDiagnosticsParams, ServerCapabilities, Url,
+};
 use serde::Serialize;
-use serde_json::Value;
+use serde_json::{json, Value};
 
-use crate::jsonrpc::{self};
+use crate::framing::{Framing, LspFraming};
+use crate::jsonrpc::{self, RequestId, ServerMessage};
+
+/// Handles a server-initiated request for `method`, returning the `result`
+/// value to send back.
+pub type RequestHandler = Box<dyn FnMut(Value) -> Value>;
+/// Handles a server-initiated notification for `method`.
+pub type NotificationHandler = Box<dyn FnMut(Value)>;
 
 pub struct Client {
     input: Box<dyn BufRead>,
     output: Box<dyn Write>,
+    framing: Box<dyn Framing>,
     request_id_counter: i64,
+    progress_ended: HashSet<String>,
+    progress_active: HashSet<String>,
+    diagnostics: HashMap<Url, PublishDiagnosticsParams>,
+    /// Set by [`Client::initialize`] once the server replies, so callers
+    /// elsewhere (e.g. the graph builder deciding whether call-hierarchy
+    /// requests are worth trying) don't each have to thread the response
+    /// through themselves.
+    capabilities: Option<ServerCapabilities>,
+    request_handlers: HashMap<String, RequestHandler>,
+    notification_handlers: HashMap<String, NotificationHandler>,
+    /// Set by [`Client::spawn`]; `Drop` kills and reaps this once the
+    /// shutdown handshake is sent, so analysis never leaves an orphaned
+    /// language server running.
+    child: Option<Child>,
 }
 
 impl Client {
-    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
-        Self {
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>, framing: Box<dyn Framing>) -> Self {
+        let mut client = Self {
             input,
             output,
+            framing,
             request_id_counter: 0,
+            progress_ended: HashSet::new(),
+            progress_active: HashSet::new(),
+            diagnostics: HashMap::new(),
+            capabilities: None,
+            request_handlers: HashMap::new(),
+            notification_handlers: HashMap::new(),
+            child: None,
+        };
+
+        // Without a reply these block the server indefinitely, so ack them by
+        // default; callers that care can override with `on_request`.
+        client.on_request("client/registerCapability", |_| json!(null));
+        client.on_request("workspace/configuration", |params| {
+            let count = params
+                .get("items")
+                .and_then(Value::as_array)
+                .map_or(1, Vec::len);
+            json!(vec![Value::Null; count])
+        });
+
+        client
+    }
+
+    /// Spawns `cmd` as the language server, wiring its stdin/stdout into an
+    /// [`LspFraming`] transport and draining its stderr line-by-line to
+    /// stderr so server diagnostics aren't lost. `Drop` sends `exit` and then
+    /// kills/reaps the child, so analysis never leaves it running.
+    pub fn spawn(cmd: Command) -> Result<Self> {
+        Self::spawn_with_logger(cmd, |line| eprintln!("stderr: {}", line))
+    }
+
+    /// Like [`Client::spawn`], but `log` is called with each line the server
+    /// writes to stderr instead of hardcoding where it goes.
+    pub fn spawn_with_logger(
+        mut cmd: Command,
+        mut log: impl FnMut(String) + Send + 'static,
+    ) -> Result<Self> {
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("spawning language server")?;
+
+        let stdin = child.stdin.take().expect("child has no stdin");
+        let stdout = BufReader::new(child.stdout.take().expect("child has no stdout"));
+        let stderr = BufReader::new(child.stderr.take().expect("child has no stderr"));
+
+        std::thread::spawn(move || {
+            for line in stderr.lines().map_while(Result::ok) {
+                log(line);
+            }
+        });
+
+        let mut client = Self::new(Box::new(stdout), Box::new(stdin), Box::new(LspFraming));
+        client.child = Some(child);
+
+        Ok(client)
+    }
+
+    /// Registers a handler for a server-initiated request named `method`.
+    /// Replaces any handler previously registered for the same method.
+    pub fn on_request(&mut self, method: &str, mut handler: impl FnMut(Value) -> Value + 'static) {
+        self.request_handlers
+            .insert(method.to_string(), Box::new(move |params| handler(params)));
+    }
+
+    /// Registers a handler for a server-initiated notification named
+    /// `method`, e.g. to collect `textDocument/publishDiagnostics`. Replaces
+    /// any handler previously registered for the same method.
+    pub fn on_notification(&mut self, method: &str, mut handler: impl FnMut(Value) + 'static) {
+        self.notification_handlers
+            .insert(method.to_string(), Box::new(move |params| handler(params)));
+    }
+
+    /// Records a `$/progress` notification: a `begin` marks the token as
+    /// in-flight (for [`Client::wait_for_indexing`]), and an `end` moves it
+    /// out of `progress_active` and into `progress_ended`, so a caller
+    /// blocked in [`Client::wait_for_progress_end`] for a token that ended
+    /// just before it started waiting doesn't miss it.
+    fn record_progress(&mut self, message: &Value) {
+        let Some(params) = message.get("params") else {
+            return;
+        };
+        let Some(token) = params.get("token").and_then(Value::as_str) else {
+            return;
+        };
+        let kind = params
+            .get("value")
+            .and_then(|value| value.get("kind"))
+            .and_then(Value::as_str);
+
+        match kind {
+            Some("begin") => {
+                self.progress_active.insert(token.to_string());
+            }
+            Some("end") => {
+                self.progress_active.remove(token);
+                self.progress_ended.insert(token.to_string());
+            }
+            _ => {}
         }
     }
 
+    /// Blocks, processing incoming messages, until a `$/progress` notification
+    /// reports `end` for `token`. Used to wait out work-done progress (e.g.
+    /// indexing) instead of guessing a fixed sleep.
+    pub fn wait_for_progress_end(&mut self, token: &str) -> Result<()> {
+        if self.progress_ended.remove(token) {
+            return Ok(());
+        }
+
+        loop {
+            let message = self.recv()?;
+            self.dispatch(message)?;
+
+            if self.progress_ended.remove(token) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Waits for the `$/progress` work the server starts after `initialize`
+    /// (e.g. indexing) to report `end`, instead of guessing a fixed sleep.
+    /// Waits for at least one token to begin and then drain, so it won't
+    /// return before indexing has even started. `timeout` is checked before
+    /// each read, so it ends the wait only while the server keeps sending
+    /// messages; a read that blocks on a server that has gone silent is not
+    /// interrupted by it.
+    pub fn wait_for_indexing(&mut self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut progress_seen = false;
+
+        loop {
+            if progress_seen && self.progress_active.is_empty() {
+                return Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                eprintln!("Timed out waiting for indexing to finish");
+                return Ok(());
+            }
+
+            let message = self.recv()?;
+            self.dispatch(message)?;
+
+            progress_seen = progress_seen || !self.progress_active.is_empty() || !self.progress_ended.is_empty();
+        }
+    }
+
+    /// Records a `textDocument/publishDiagnostics` notification, replacing
+    /// whatever was previously known about that file -- servers resend the
+    /// full set each time, not a diff.
+    fn record_diagnostics(&mut self, params: &Value) {
+        if let Ok(params) = serde_json::from_value::<PublishDiagnosticsParams>(params.clone()) {
+            self.diagnostics.insert(params.uri.clone(), params);
+        }
+    }
+
+    /// The most recent `textDocument/publishDiagnostics` the server has sent
+    /// for each file, keyed by file URI. Empty for a file the server hasn't
+    /// reported on yet.
+    pub fn diagnostics(&self) -> &HashMap<Url, PublishDiagnosticsParams> {
+        &self.diagnostics
+    }
+
+    /// The server's capabilities, as reported in its `initialize` response.
+    /// `None` until [`Client::initialize`] has been called.
+    pub fn capabilities(&self) -> Option<&ServerCapabilities> {
+        self.capabilities.as_ref()
+    }
+
+    /// Records the capabilities from an `initialize` response. Called by
+    /// [`crate::lsp_facade`]'s `Client::initialize`, which lives in a
+    /// separate module and so can't set the private field directly.
+    pub(crate) fn set_capabilities(&mut self, capabilities: ServerCapabilities) {
+        self.capabilities = Some(capabilities);
+    }
+
     pub fn notify<N: Notification>(&mut self, params: Option<N::Params>) -> Result<()> {
         let notification = jsonrpc::Notification {
             jsonrpc: "2.0".to_string(),
@@ -32,26 +238,71 @@ impl Client {
         self.send(&notification)
     }
 
+    /// Classifies and dispatches a single message received from the server.
+    /// Returns the message as a `Value` if it is a response, whatever its id;
+    /// the caller checks whether it is the reply it was waiting for.
+    fn dispatch(&mut self, message: Value) -> Result<Option<Value>> {
+        let classified: ServerMessage =
+            serde_json::from_value(message.clone()).context("classifying incoming message")?;
+
+        match classified {
+            ServerMessage::Response(_) => Ok(Some(message)),
+            ServerMessage::Request(request) => {
+                let result = match self.request_handlers.get_mut(&request.method) {
+                    Some(handler) => handler(request.params),
+                    None => json!(null),
+                };
+
+                let response = json!({
+                    "jsonrpc": "2.0",
+                    "id": request.id,
+                    "result": result,
+                });
+                self.send(&response).context("replying to server request")?;
+
+                Ok(None)
+            }
+            ServerMessage::Notification(notification) => {
+                if notification.method == "$/progress" {
+                    self.record_progress(&message);
+                }
+
+                if notification.method == "textDocument/publishDiagnostics" {
+                    self.record_diagnostics(&notification.params);
+                }
+
+                if let Some(handler) = self.notification_handlers.get_mut(&notification.method) {
+                    handler(notification.params);
+                }
+
+                Ok(None)
+            }
+        }
+    }
+
     pub fn request<R: Request>(&mut self, params: Option<R::Params>) -> Result<R::Result> {
+        let id = RequestId::Number(self.request_id_counter);
         let request = jsonrpc::Request {
             jsonrpc: "2.0".to_string(),
             method: R::METHOD.to_string(),
             params,
-            id: self.request_id_counter,
+            id: id.clone(),
         };
 
         self.send(&request)?;
 
         let response: jsonrpc::Response<_> = loop {
-            let response = self.recv()?;
+            let message = self.recv()?;
+
+            let Some(value) = self.dispatch(message)? else {
+                continue;
+            };
 
             // check if this is our response
-            if response.get("method").is_none()
-                && response
-                    .get("id")
-                    .is_some_and(|id| id.as_i64() == Some(self.request_id_counter))
-            {
-                break serde_json::from_value(response)?;
+            if value.get("id").is_some_and(|value_id| {
+                serde_json::from_value::<RequestId>(value_id.clone()).is_ok_and(|value_id| value_id == id)
+            }) {
+                break serde_json::from_value(value)?;
             }
         };
 
@@ -61,42 +312,35 @@ impl Client {
     }
 
     fn send(&mut self, msg: &impl Serialize) -> Result<()> {
-        let msg = serde_json::to_string(msg)?;
-
-        let length = msg.as_bytes().len();
-        let msg = &format!("Content-Length: {}\r\n\r\n{}", length, msg);
+        let msg = serde_json::to_string(msg).context("serializing message")?;
 
-        self.output
-            .write_all(msg.as_bytes())
-            .context("writing msg to output")
+        self.framing
+            .write_message(self.output.as_mut(), msg.as_bytes())
+            .context("writing message to output")
     }
 
     fn recv(&mut self) -> Result<Value> {
-        let mut content_length = None;
-
-        loop {
-            let mut line = String::new();
-            self.input.read_line(&mut line)?;
-
-            let words: Vec<_> = line.split_ascii_whitespace().collect();
+        let content = self
+            .framing
+            .read_message(self.input.as_mut())
+            .context("reading message from input")?;
 
-            match (words.as_slice(), &content_length) {
-                (["Content-Length:", c_length], None) => content_length = Some(c_length.parse()?),
-                (["Content-Type:", _], Some(_)) => {}
-                ([], Some(content_length)) => {
-                    let mut content = Vec::with_capacity(*content_length);
+        serde_json::from_slice(&content).context("deserializing message")
+    }
+}
 
-                    // make sure we don't seek past the current message
-                    let mut bytes_left = *content_length;
-                    while bytes_left > 0 {
-                        let read_bytes = self.input.read_until(b'}', &mut content)?;
-                        bytes_left -= read_bytes;
-                    }
+impl Drop for Client {
+    /// Runs the LSP shutdown handshake: `shutdown` then `exit`. Best-effort,
+    /// since a server that already went away can't be told anything. If this
+    /// `Client` owns the server process (constructed via [`Client::spawn`]),
+    /// also kills and reaps it afterwards so it can't outlive the `Client`.
+    fn drop(&mut self) {
+        let _ = self.request::<Shutdown>(None);
+        let _ = self.notify::<Exit>(None);
 
-                    return serde_json::from_slice(&content).context("deserializing response");
-                }
-                unexpected => panic!("Got unexpected stdout: {:?}", unexpected),
-            };
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
         }
     }
 }
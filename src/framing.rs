@@ -0,0 +1,74 @@
+use std::io::{BufRead, Write};
+
+use anyhow::{Context, Result};
+
+/// How [`crate::Client`] delimits one JSON-RPC message from the next on the
+/// wire, so the same request/response core can drive an LSP server or a
+/// simpler line-delimited RPC peer.
+pub trait Framing {
+    fn read_message(&self, input: &mut dyn BufRead) -> Result<Vec<u8>>;
+    fn write_message(&self, output: &mut dyn Write, body: &[u8]) -> Result<()>;
+}
+
+/// The LSP base protocol: a block of header lines (only `Content-Length` is
+/// required, `Content-Type` is accepted and ignored), a blank separator
+/// line, then exactly `Content-Length` bytes of body.
+pub struct LspFraming;
+
+impl Framing for LspFraming {
+    fn read_message(&self, input: &mut dyn BufRead) -> Result<Vec<u8>> {
+        let mut content_length = None;
+
+        loop {
+            let mut line = String::new();
+            input.read_line(&mut line).context("reading header line")?;
+
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                break;
+            }
+
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = Some(value.trim().parse().context("parsing Content-Length")?);
+            }
+            // Any other header (e.g. Content-Type) is accepted and ignored.
+        }
+
+        let mut content = vec![0u8; content_length.context("missing Content-Length")?];
+        input
+            .read_exact(&mut content)
+            .context("reading message body")?;
+
+        Ok(content)
+    }
+
+    fn write_message(&self, output: &mut dyn Write, body: &[u8]) -> Result<()> {
+        output
+            .write_all(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())
+            .context("writing header")?;
+
+        output.write_all(body).context("writing body")
+    }
+}
+
+/// Newline-delimited JSON: one `\n`-terminated message per line, the wire
+/// format some simpler JSON-RPC backends use instead of LSP's headers.
+pub struct NdjsonFraming;
+
+impl Framing for NdjsonFraming {
+    fn read_message(&self, input: &mut dyn BufRead) -> Result<Vec<u8>> {
+        let mut line = String::new();
+        input.read_line(&mut line).context("reading ndjson line")?;
+
+        Ok(line.trim_end_matches(['\r', '\n']).as_bytes().to_vec())
+    }
+
+    fn write_message(&self, output: &mut dyn Write, body: &[u8]) -> Result<()> {
+        output.write_all(body).context("writing body")?;
+        output.write_all(b"\n").context("writing newline")
+    }
+}
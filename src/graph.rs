@@ -1,11 +1,106 @@
 use std::collections::HashSet;
+use std::time::Duration;
 
 use anyhow::Result;
-use lsp_types::{SymbolKind, Url};
+use lsp_types::{CallHierarchyItem, DiagnosticSeverity, SymbolKind, Url};
 use serde_json::{json, Value};
 
 use crate::Client;
 
+/// How long to wait for indexing if the server never reports `$/progress`
+/// at all. Checked as messages arrive, see [`Client::wait_for_indexing`].
+const INDEXING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether the server has reported any error-severity diagnostic for `uri`,
+/// which makes `references`/`definitions` results for that file unreliable.
+fn has_errors(client: &Client, uri: &Url) -> bool {
+    client
+        .diagnostics()
+        .get(uri)
+        .is_some_and(|params| {
+            params
+                .diagnostics
+                .iter()
+                .any(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+        })
+}
+
+/// Key identifying a [`CallHierarchyItem`] uniquely enough to dedupe edges
+/// and items across overlapping `incomingCalls` results.
+fn call_hierarchy_item_key(item: &CallHierarchyItem) -> String {
+    format!(
+        "{}#{}@{}:{}",
+        item.uri,
+        item.name,
+        item.selection_range.start.line,
+        item.selection_range.start.character
+    )
+}
+
+fn call_hierarchy_item_json(item: &CallHierarchyItem) -> Value {
+    json!({
+        "file": item.uri,
+        "name": item.name,
+        "range": item.selection_range,
+    })
+}
+
+/// Builds symbol-to-symbol call edges via `callHierarchy/incomingCalls`,
+/// which gives exact caller/callee pairs and call-site ranges instead of the
+/// file-level over-approximation plain `references` gives. Starts from every
+/// definition in `symbol_mask` and walks callers in, ignoring ones outside
+/// `root`.
+fn build_call_hierarchy_edges(
+    client: &mut Client,
+    root: &Url,
+    uris: &[Url],
+    symbol_mask: &[SymbolKind],
+) -> Result<(serde_json::Map<String, Value>, Vec<Value>)> {
+    let mut items = serde_json::Map::new();
+    let mut edges = vec![];
+    let mut seen_edges = HashSet::new();
+
+    for uri in uris {
+        for symbol in client.symbols(uri)? {
+            if !symbol_mask.contains(&symbol.kind) {
+                continue;
+            }
+
+            for callee in client.prepare_call_hierarchy(uri, &symbol)? {
+                let callee_key = call_hierarchy_item_key(&callee);
+                items
+                    .entry(callee_key.clone())
+                    .or_insert_with(|| call_hierarchy_item_json(&callee));
+
+                for caller in client.incoming_calls(&callee)? {
+                    // ignore callers outside of project files
+                    if !caller.uri.as_str().starts_with(root.as_str()) {
+                        continue;
+                    }
+
+                    // the file containing the call site failed to compile, so
+                    // this call can't be trusted
+                    if has_errors(client, &caller.uri) {
+                        continue;
+                    }
+
+                    let caller_key = call_hierarchy_item_key(&caller);
+                    items
+                        .entry(caller_key.clone())
+                        .or_insert_with(|| call_hierarchy_item_json(&caller));
+
+                    if seen_edges.insert((caller_key.clone(), callee_key.clone())) {
+                        eprintln!("Found call: {} -> {}", caller_key, callee_key);
+                        edges.push(json!({ "from": caller_key, "to": callee_key }));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok((items, edges))
+}
+
 pub fn build_graph(client: &mut Client, root: &Url, uris: &[Url]) -> Result<Value> {
     // only use these kinds of symbols
     let symbol_mask = [
@@ -20,11 +115,10 @@ pub fn build_graph(client: &mut Client, root: &Url, uris: &[Url]) -> Result<Valu
         client.open(&uri, &std::fs::read_to_string(uri.path())?)?;
     }
 
-    eprintln!("Waiting 3 seconds for LSP to index code...");
-    std::thread::sleep(std::time::Duration::from_secs(3));
+    eprintln!("Waiting for LSP to finish indexing...");
+    client.wait_for_indexing(INDEXING_TIMEOUT)?;
 
-    let mut nodes = HashSet::new();
-    let mut edges = HashSet::new();
+    let mut nodes = serde_json::Map::new();
 
     for uri in uris {
         // ignore uri not under root
@@ -32,28 +126,77 @@ pub fn build_graph(client: &mut Client, root: &Url, uris: &[Url]) -> Result<Valu
             continue;
         };
 
-        nodes.insert(node);
+        let diagnostic_counts = client.diagnostics().get(uri).map_or_else(
+            || json!({"errors": 0, "warnings": 0}),
+            |params| {
+                let errors = params
+                    .diagnostics
+                    .iter()
+                    .filter(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+                    .count();
+                let warnings = params
+                    .diagnostics
+                    .iter()
+                    .filter(|d| d.severity == Some(DiagnosticSeverity::WARNING))
+                    .count();
+                json!({"errors": errors, "warnings": warnings})
+            },
+        );
+        nodes.insert(node.to_string(), diagnostic_counts);
+    }
+
+    // call hierarchy gives precise symbol-to-symbol edges with call-site
+    // ranges; fall back to file-to-file reference matching for servers that
+    // don't support it.
+    if client
+        .capabilities()
+        .is_some_and(|capabilities| capabilities.call_hierarchy_provider.is_some())
+    {
+        let (calls, edges) = build_call_hierarchy_edges(client, root, uris, &symbol_mask)?;
+
+        return Ok(json!({
+            "root": root,
+            "nodes": nodes,
+            "calls": calls,
+            "edges": edges,
+        }));
+    }
+
+    eprintln!("Server has no call hierarchy support, falling back to references");
 
-        for symbol in client.symbols(&uri)? {
+    let mut edges = HashSet::new();
+
+    for uri in uris {
+        let Some(node) = uri.as_str().strip_prefix(root.as_str()) else {
+            continue;
+        };
+
+        for symbol in client.symbols(uri)? {
             if !symbol_mask.contains(&symbol.kind) {
                 continue;
             }
 
             // ignore symbols defined outside of project root
             if !client
-                .goto_definition(&uri, &symbol)?
+                .goto_definition(uri, &symbol)?
                 .iter()
                 .any(|d| d.as_str().starts_with(root.as_str()))
             {
                 continue;
             }
 
-            for reference in client.references(&uri, &symbol)? {
+            for reference in client.references(uri, &symbol)? {
                 // ignore references outside of project files
                 if reference == *uri || !uris.contains(&reference) {
                     continue;
                 }
 
+                // the file containing the reference failed to compile, so its
+                // reported references can't be trusted
+                if has_errors(client, &reference) {
+                    continue;
+                }
+
                 let reference_node = reference.as_str().strip_prefix(root.as_str()).unwrap();
                 eprintln!("Found reference: {} -> {}", reference_node, node);
 
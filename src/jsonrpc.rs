@@ -1,13 +1,40 @@
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::Value;
 
+/// A JSON-RPC id: the spec allows either a number or a string, and peers
+/// that aren't our own LSP servers may send string ones. Carrying this
+/// instead of a bare `i64` lets ids be compared structurally rather than
+/// coerced through `as_i64`, which silently treats a string id the same as
+/// "no id at all".
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum RequestId {
+    Number(i64),
+    String(String),
+}
+
+impl From<i64> for RequestId {
+    fn from(id: i64) -> Self {
+        RequestId::Number(id)
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestId::Number(id) => write!(f, "{id}"),
+            RequestId::String(id) => write!(f, "{id}"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Request<Params> {
     pub jsonrpc: String,
     pub method: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub params: Option<Params>,
-    pub id: i64,
+    pub id: RequestId,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -24,7 +51,7 @@ pub struct Response<T: Serialize + DeserializeOwned> {
     #[serde(flatten)]
     #[serde(with = "JsonRpcResult")]
     pub result: Result<T, Error>,
-    pub id: Option<i64>,
+    pub id: Option<RequestId>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -54,6 +81,40 @@ impl std::fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+/// A frame received off the server, classified by shape rather than by a tag
+/// in the payload (the base protocol doesn't have one): a reply to one of our
+/// requests, a request the server wants *us* to answer, or a one-way
+/// notification.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ServerMessage {
+    Response(Response<Value>),
+    Request(ServerRequest),
+    Notification(ServerNotification),
+}
+
+/// A server-initiated request, e.g. `client/registerCapability` or
+/// `workspace/configuration`. Requires a `Response` to be sent back carrying
+/// the same `id`.
+#[derive(Deserialize, Debug)]
+pub struct ServerRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    pub id: RequestId,
+}
+
+/// A server-initiated notification, e.g. `window/logMessage` or
+/// `textDocument/publishDiagnostics`.
+#[derive(Deserialize, Debug)]
+pub struct ServerNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::json;
@@ -67,7 +128,7 @@ mod tests {
                 jsonrpc: "2.0".to_string(),
                 method: "subtract".to_string(),
                 params: Some(vec![42, 23]),
-                id: 1,
+                id: RequestId::Number(1),
             },
             @r#"{"jsonrpc": "2.0", "method": "subtract", "params": [42, 23], "id": 1}"#
         );
@@ -80,7 +141,7 @@ mod tests {
                 jsonrpc: "2.0".to_string(),
                 method: "method".to_string(),
                 params: Some(()),
-                id: 1,
+                id: RequestId::Number(1),
             },
             @r###"{"jsonrpc": "2.0", "method": "method", "params": null, "id": 1}"###
         );
@@ -90,10 +151,20 @@ mod tests {
                 jsonrpc: "2.0".to_string(),
                 method: "method".to_string(),
                 params: None,
-                id: 1,
+                id: RequestId::Number(1),
             },
             @r#"{"jsonrpc": "2.0", "method": "method", "id": 1}"#
         );
+
+        insta::assert_compact_json_snapshot!(
+            Request {
+                jsonrpc: "2.0".to_string(),
+                method: "method".to_string(),
+                params: Some(vec![42, 23]),
+                id: RequestId::String("request-1".to_string()),
+            },
+            @r#"{"jsonrpc": "2.0", "method": "method", "params": [42, 23], "id": "request-1"}"#
+        );
     }
 
     #[test]
@@ -111,7 +182,9 @@ mod tests {
                         23,
                     ],
                 ),
-                id: 1,
+                id: Number(
+                    1,
+                ),
             },
         )
         "###
@@ -157,7 +230,7 @@ mod tests {
             Response {
                 jsonrpc: "2.0".to_string(),
                 result: Ok(19),
-                id: Some(1),
+                id: Some(RequestId::Number(1)),
             },
             @r###"{"jsonrpc": "2.0", "result": 19, "id": 1}"###
         );
@@ -188,7 +261,9 @@ mod tests {
                     19,
                 ),
                 id: Some(
-                    1,
+                    Number(
+                        1,
+                    ),
                 ),
             },
         )
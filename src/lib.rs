@@ -1,7 +1,9 @@
+mod client;
+mod framing;
 mod graph;
 mod jsonrpc;
-mod lsp;
 mod lsp_facade;
 
+pub use client::Client;
+pub use framing::{Framing, LspFraming, NdjsonFraming};
 pub use graph::build_graph;
-pub use lsp::Client;
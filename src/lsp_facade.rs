@@ -1,8 +1,12 @@
 use anyhow::Result;
 use lsp_types::{
     notification::{DidOpenTextDocument, Initialized},
-    request::{DocumentSymbolRequest, GotoDefinition, Initialize, References},
-    DocumentSymbol, DocumentSymbolResponse, GotoDefinitionResponse, ServerCapabilities, Url,
+    request::{
+        CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare,
+        DocumentSymbolRequest, GotoDefinition, Initialize, References,
+    },
+    CallHierarchyItem, DocumentSymbol, DocumentSymbolResponse, GotoDefinitionResponse,
+    ServerCapabilities, Url,
 };
 use serde_json::json;
 
@@ -113,6 +117,54 @@ impl Client {
         Ok(symbols)
     }
 
+    pub fn prepare_call_hierarchy(
+        &mut self,
+        uri: &Url,
+        symbol: &DocumentSymbol,
+    ) -> Result<Vec<CallHierarchyItem>> {
+        let items = self.request::<CallHierarchyPrepare>(
+            serde_json::from_value(json!(
+                {
+                    "textDocument": {
+                        "uri": uri,
+                    },
+                    "position": symbol.selection_range.start,
+                }
+            ))
+            .unwrap(),
+        )?;
+
+        Ok(items.unwrap_or_default())
+    }
+
+    pub fn incoming_calls(&mut self, item: &CallHierarchyItem) -> Result<Vec<CallHierarchyItem>> {
+        let calls = self.request::<CallHierarchyIncomingCalls>(
+            serde_json::from_value(json!({ "item": item })).unwrap(),
+        )?;
+
+        Ok(calls
+            .unwrap_or_default()
+            .into_iter()
+            .map(|call| call.from)
+            .collect())
+    }
+
+    pub fn outgoing_calls(&mut self, item: &CallHierarchyItem) -> Result<Vec<CallHierarchyItem>> {
+        let calls = self.request::<CallHierarchyOutgoingCalls>(
+            serde_json::from_value(json!({ "item": item })).unwrap(),
+        )?;
+
+        Ok(calls
+            .unwrap_or_default()
+            .into_iter()
+            .map(|call| call.to)
+            .collect())
+    }
+
+    /// Sends `initialize`, advertising only the capabilities this crate
+    /// actually exercises (document symbols, call hierarchy, references,
+    /// go-to-definition and work-done progress), then sends `initialized`
+    /// once the server replies.
     pub fn initialize(&mut self, uri: Url) -> Result<ServerCapabilities> {
         let response = self.request::<Initialize>(
             serde_json::from_value(json!(
@@ -121,7 +173,19 @@ impl Client {
                         "textDocument": {
                             "documentSymbol": {
                                 "hierarchicalDocumentSymbolSupport": true,
-                            }
+                            },
+                            "callHierarchy": {
+                                "dynamicRegistration": false,
+                            },
+                            "references": {
+                                "dynamicRegistration": false,
+                            },
+                            "definition": {
+                                "dynamicRegistration": false,
+                            },
+                        },
+                        "window": {
+                            "workDoneProgress": true,
                         },
                     },
                     "workspaceFolders": [
@@ -137,6 +201,8 @@ impl Client {
 
         self.notify::<Initialized>(None)?;
 
+        self.set_capabilities(response.capabilities.clone());
+
         Ok(response.capabilities)
     }
 }